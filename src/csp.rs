@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: MIT
+
+use base64::{
+	engine::general_purpose::STANDARD,
+	Engine,
+};
+use clap::ValueEnum;
+use indexmap::IndexSet;
+use sha2::{
+	Digest,
+	Sha256,
+};
+
+use crate::markup::{
+	find_ignore_case,
+	unescape,
+};
+
+/// A value for the `referrer` meta tag.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, ValueEnum)]
+pub enum ReferrerPolicy {
+	NoReferrer,
+	NoReferrerWhenDowngrade,
+	Origin,
+	OriginWhenCrossOrigin,
+	SameOrigin,
+	StrictOrigin,
+	StrictOriginWhenCrossOrigin,
+	UnsafeUrl,
+}
+
+impl ReferrerPolicy {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Self::NoReferrer => "no-referrer",
+			Self::NoReferrerWhenDowngrade => "no-referrer-when-downgrade",
+			Self::Origin => "origin",
+			Self::OriginWhenCrossOrigin => "origin-when-cross-origin",
+			Self::SameOrigin => "same-origin",
+			Self::StrictOrigin => "strict-origin",
+			Self::StrictOriginWhenCrossOrigin => "strict-origin-when-cross-origin",
+			Self::UnsafeUrl => "unsafe-url",
+		}
+	}
+}
+
+/// Returns the CSP source matching a URL: its origin, or `'self'` for relative
+/// URLs.
+fn source(url: &str) -> String {
+	let url = url.trim();
+	if let Some(rest) = url.strip_prefix("//") {
+		// Protocol-relative
+		let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+		return host.to_owned();
+	}
+
+	match url.split_once("://") {
+		Some((scheme, rest)) if !scheme.contains(['/', '?', '#']) => {
+			let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+			format!("{}://{}", scheme.to_ascii_lowercase(), host)
+		}
+		// `data:` and the like
+		_ => match url.split_once(':') {
+			Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => {
+				format!("{}:", scheme.to_ascii_lowercase())
+			}
+			_ => "'self'".into(),
+		},
+	}
+}
+
+fn hash(s: &str) -> String {
+	format!("'sha256-{}'", STANDARD.encode(Sha256::digest(s.as_bytes())))
+}
+
+/// Returns the attributes and the contents of the inline `<tag>` elements in
+/// `html`; elements with a `src` attribute are skipped.
+fn inline_blocks<'a>(html: &'a str, tag: &str) -> Vec<(&'a str, &'a str)> {
+	let open = format!("<{tag}");
+	let close = format!("</{tag}>");
+	let mut blocks = Vec::new();
+	let mut rest = html;
+
+	while let Some(i) = find_ignore_case(rest, &open) {
+		rest = &rest[i + open.len()..];
+		if !rest.starts_with(|c: char| c == '>' || c.is_whitespace()) {
+			continue;
+		}
+		let Some(end) = rest.find('>') else { break };
+		let attrs = &rest[..end];
+		rest = &rest[end + 1..];
+
+		let Some(len) = find_ignore_case(rest, &close) else {
+			break;
+		};
+		if find_ignore_case(attrs, "src=").is_none() {
+			blocks.push((attrs, &rest[..len]));
+		}
+		rest = &rest[len + close.len()..];
+	}
+
+	blocks
+}
+
+/// Returns the values of the `name` attributes in `html`.
+fn attribute_values<'a>(html: &'a str, name: &str) -> Vec<&'a str> {
+	let pat = format!(" {name}=\"");
+	let mut values = Vec::new();
+	let mut rest = html;
+	while let Some(i) = rest.find(&pat) {
+		rest = &rest[i + pat.len()..];
+		let end = rest.find('"').unwrap_or(rest.len());
+		values.push(&rest[..end]);
+		rest = &rest[end..];
+	}
+	values
+}
+
+/// Builds a Content Security Policy allowing the stylesheets, scripts and
+/// images of a page, and the inline scripts and styles in its head and body.
+///
+/// `head` holds the parts of the head that may have inline scripts and
+/// styles. If `inline_styles` is set, any inline style is allowed; scripts
+/// such as Mermaid add styles at runtime. `output` returns the contents of a
+/// script or style as written to the page, given the tag name and the `type`
+/// attribute.
+pub fn policy<'a>(
+	css: impl Iterator<Item = &'a str>,
+	scripts: impl Iterator<Item = &'a str>,
+	head: &[&str],
+	body: &str,
+	inline_styles: bool,
+	output: impl Fn(&str, &str, &str) -> String,
+) -> String {
+	let mut script_src = IndexSet::from(["'self'".to_owned()]);
+	let mut style_src = script_src.clone();
+	let mut img_src = script_src.clone();
+
+	script_src.extend(scripts.map(source));
+	style_src.extend(css.map(source));
+
+	let hash_block = |tag: &str, (attrs, content): (&str, &str)| {
+		let ty = attribute_values(attrs, "type").first().copied();
+		hash(&output(tag, ty.unwrap_or_default(), content))
+	};
+
+	for &html in head.iter().chain([&body]) {
+		script_src.extend(
+			inline_blocks(html, "script")
+				.into_iter()
+				.map(|b| hash_block("script", b)),
+		);
+		if !inline_styles {
+			style_src.extend(
+				inline_blocks(html, "style")
+					.into_iter()
+					.map(|b| hash_block("style", b)),
+			);
+		}
+	}
+
+	// Style attributes, such as the ones used to align table columns
+	let styles = attribute_values(body, "style");
+	if inline_styles {
+		// Browsers ignore 'unsafe-inline' if there are hashes
+		style_src.insert("'unsafe-inline'".into());
+	} else if !styles.is_empty() {
+		style_src.insert("'unsafe-hashes'".into());
+		// The HTML writer escapes attribute values; the browser hashes them
+		// unescaped
+		style_src.extend(styles.into_iter().map(|s| hash(&unescape(s))));
+	}
+
+	for tag in ["img", "source"] {
+		let mut rest = body;
+		let open = format!("<{tag} ");
+		while let Some(i) = rest.find(&open) {
+			rest = &rest[i..];
+			let end = rest.find('>').unwrap_or(rest.len());
+			img_src.extend(
+				attribute_values(&rest[..end], "src")
+					.into_iter()
+					.map(|s| source(&unescape(s))),
+			);
+			rest = &rest[end..];
+		}
+	}
+
+	let join = |set: IndexSet<String>| set.into_iter().collect::<Vec<_>>().join(" ");
+	format!(
+		"default-src 'self'; script-src {}; style-src {}; img-src {}; object-src 'none'; base-uri 'self'; form-action 'self'",
+		join(script_src),
+		join(style_src),
+		join(img_src)
+	)
+}
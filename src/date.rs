@@ -0,0 +1,160 @@
+// SPDX-License-Identifier: MIT
+
+use std::time::{
+	SystemTime,
+	UNIX_EPOCH,
+};
+
+/// A point in time, in seconds since the Unix epoch.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct Timestamp(pub i64);
+
+impl Timestamp {
+	pub fn now() -> Self {
+		let secs = match SystemTime::now().duration_since(UNIX_EPOCH) {
+			Ok(d) => i64::try_from(d.as_secs()).unwrap_or(i64::MAX),
+			Err(e) => -i64::try_from(e.duration().as_secs()).unwrap_or(i64::MAX),
+		};
+		Self(secs)
+	}
+
+	/// Parses a date in the `YYYY-MM-DD` format, optionally followed by a time
+	/// and a UTC offset, such as `2024-05-01T10:30:00+02:00`.
+	///
+	/// Times without an offset are in UTC, and dates without a time refer to
+	/// the start of the day.
+	pub fn parse(s: &str) -> Result<Self, String> {
+		let s = s.trim();
+		Self::parse_inner(s).ok_or_else(|| {
+			format!(
+				"invalid date `{s}`: expected YYYY-MM-DD, optionally followed by a time such as 10:30, 10:30:00Z or 10:30:00+02:00"
+			)
+		})
+	}
+
+	fn parse_inner(s: &str) -> Option<Self> {
+		let (date, time) = match s.find(['T', 't', ' ']) {
+			Some(i) => (&s[..i], s[i + 1..].trim_start()),
+			None => (s, ""),
+		};
+
+		let mut parts = date.splitn(3, '-');
+		let y = number(parts.next()?, 4)?;
+		let m = number(parts.next()?, 2)?;
+		let d = number(parts.next()?, 2)?;
+		if !(1..=12).contains(&m) || d == 0 || d > days_in_month(y, m) {
+			return None;
+		}
+
+		let mut secs = days_from_civil(y, m, d) * 86400;
+		if time.is_empty() {
+			return Some(Self(secs));
+		}
+
+		let (time, offset) = match time.find(['Z', 'z', '+', '-']) {
+			None => (time, 0),
+			Some(i) => (&time[..i], parse_offset(&time[i..])?),
+		};
+
+		let mut parts = time.splitn(3, ':');
+		let h = number(parts.next()?, 2)?;
+		let min = number(parts.next()?, 2)?;
+		// Fractions of a second are ignored
+		let sec = match parts.next() {
+			None => 0,
+			Some(s) => number(s.split_once('.').map_or(s, |(s, _)| s), 2)?,
+		};
+		if h > 23 || min > 59 || sec > 60 {
+			return None;
+		}
+
+		secs += h * 3600 + min * 60 + sec - offset;
+		Some(Self(secs))
+	}
+
+	/// Formats the time in UTC, such as `2024-05-01T08:30:00Z`.
+	pub fn to_utc_string(self) -> String {
+		let (days, secs) = (self.0.div_euclid(86400), self.0.rem_euclid(86400));
+		let (y, m, d) = civil_from_days(days);
+		format!(
+			"{y:04}-{m:02}-{d:02}T{:02}:{:02}:{:02}Z",
+			secs / 3600,
+			secs / 60 % 60,
+			secs % 60
+		)
+	}
+}
+
+/// Parses a number of exactly `len` ASCII digits.
+fn number(s: &str, len: usize) -> Option<i64> {
+	if s.len() == len && s.bytes().all(|b| b.is_ascii_digit()) {
+		s.parse().ok()
+	} else {
+		None
+	}
+}
+
+/// Parses `Z`, `+HH`, `+HH:MM` or `+HHMM`, returning the offset in seconds.
+fn parse_offset(s: &str) -> Option<i64> {
+	if s.eq_ignore_ascii_case("z") {
+		return Some(0);
+	}
+
+	let (sign, rest) = match s.as_bytes().first()? {
+		b'+' => (1, &s[1..]),
+		b'-' => (-1, &s[1..]),
+		_ => return None,
+	};
+
+	let (h, m) = match rest.split_once(':') {
+		Some((h, m)) => (number(h, 2)?, number(m, 2)?),
+		None if rest.len() == 4 && rest.is_ascii() => {
+			(number(&rest[..2], 2)?, number(&rest[2..], 2)?)
+		}
+		None => (number(rest, 2)?, 0),
+	};
+
+	Some(sign * (h * 3600 + m * 60))
+}
+
+fn is_leap_year(y: i64) -> bool {
+	y % 4 == 0 && (y % 100 != 0 || y % 400 == 0)
+}
+
+fn days_in_month(y: i64, m: i64) -> i64 {
+	match m {
+		2 if is_leap_year(y) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31,
+	}
+}
+
+/// Returns the number of days since 1970-01-01.
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+	let y = if m <= 2 { y - 1 } else { y };
+	let era = y.div_euclid(400);
+	let yoe = y - era * 400;
+	let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	era * 146097 + doe - 719468
+}
+
+/// Returns the date `days` days after 1970-01-01; the inverse of
+/// [days_from_civil].
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let d = doy - (153 * mp + 2) / 5 + 1;
+	let m = if mp < 10 { mp + 3 } else { mp - 9 };
+	let y = yoe + era * 400 + i64::from(m <= 2);
+	(y, m, d)
+}
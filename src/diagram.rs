@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	io::{
+		ErrorKind,
+		Write,
+	},
+	process::{
+		Command,
+		Stdio,
+	},
+	sync::atomic::{
+		AtomicBool,
+		Ordering,
+	},
+	thread,
+};
+
+use log::warn;
+use pulldown_cmark::{
+	CodeBlockKind,
+	CowStr,
+	Event,
+	Tag,
+	TagEnd,
+};
+
+/// The script that renders the Mermaid diagrams on a page, pinned to a version
+/// so that its integrity hash stays valid.
+pub const MERMAID_SCRIPT: &str = "https://cdn.jsdelivr.net/npm/mermaid@11.4.1/dist/mermaid.min.js";
+
+/// Set once we know the `dot` command is not installed.
+static NO_DOT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+	Mermaid,
+	Dot,
+}
+
+fn kind(info: &str) -> Option<Kind> {
+	match info.split_whitespace().next()? {
+		"mermaid" => Some(Kind::Mermaid),
+		"dot" | "graphviz" => Some(Kind::Dot),
+		_ => None,
+	}
+}
+
+/// Replaces the `mermaid` code blocks with the markup the Mermaid script looks
+/// for, and renders the `dot` and `graphviz` code blocks to inline SVG.
+///
+/// Graphviz diagrams are left as code blocks if `dot` is not set, or the `dot`
+/// command is not available or fails. Returns whether there are Mermaid
+/// diagrams.
+pub fn render(events: &mut Vec<Event>, dot: bool) -> bool {
+	let mut has_mermaid = false;
+	let mut out = Vec::with_capacity(events.len());
+	let mut iter = std::mem::take(events).into_iter();
+
+	while let Some(e) = iter.next() {
+		let kind = match &e {
+			Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => kind(info),
+			_ => None,
+		};
+		let Some(kind) = kind else {
+			out.push(e);
+			continue;
+		};
+
+		let mut block = vec![e];
+		let mut code = String::new();
+		for e in iter.by_ref() {
+			let end = matches!(e, Event::End(TagEnd::CodeBlock));
+			if let Event::Text(s) = &e {
+				code.push_str(s);
+			}
+			block.push(e);
+			if end {
+				break;
+			}
+		}
+
+		match kind {
+			Kind::Mermaid => {
+				has_mermaid = true;
+				// The text is escaped by the HTML writer
+				out.push(Event::Html(CowStr::Borrowed("<pre class=\"mermaid\">")));
+				out.push(Event::Text(code.into()));
+				out.push(Event::Html(CowStr::Borrowed("</pre>\n")));
+			}
+			Kind::Dot => match dot.then(|| render_dot(&code)).flatten() {
+				Some(svg) => out.push(Event::Html(
+					format!("<div class=\"graphviz\">{svg}</div>\n").into(),
+				)),
+				None => out.append(&mut block),
+			},
+		}
+	}
+
+	*events = out;
+	has_mermaid
+}
+
+/// Renders a Graphviz diagram to SVG using the `dot` command.
+fn render_dot(code: &str) -> Option<String> {
+	if NO_DOT.load(Ordering::Relaxed) {
+		return None;
+	}
+
+	let mut child = match Command::new("dot")
+		.arg("-Tsvg")
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+	{
+		Ok(c) => c,
+		Err(e) => {
+			if !NO_DOT.swap(true, Ordering::Relaxed) {
+				if e.kind() == ErrorKind::NotFound {
+					warn!("the dot command is not installed; Graphviz diagrams are left as code blocks");
+				} else {
+					warn!("failed to run dot: {e}; Graphviz diagrams are left as code blocks");
+				}
+			}
+			return None;
+		}
+	};
+
+	let mut stdin = child.stdin.take()?;
+	// Write from another thread so that a large output can't block us
+	let output = thread::scope(|s| {
+		s.spawn(move || stdin.write_all(code.as_bytes()));
+		child.wait_with_output()
+	});
+
+	match output {
+		Ok(o) if o.status.success() => {
+			let svg = String::from_utf8_lossy(&o.stdout);
+			// Drop the XML declaration and the doctype
+			let start = svg.find("<svg").unwrap_or(0);
+			Some(svg[start..].trim_end().to_owned())
+		}
+		Ok(o) => {
+			let msg = String::from_utf8_lossy(&o.stderr);
+			warn!(
+				"failed to render a Graphviz diagram: {}",
+				msg.lines().next().unwrap_or("dot failed").trim()
+			);
+			None
+		}
+		Err(e) => {
+			warn!("failed to render a Graphviz diagram: {e}");
+			None
+		}
+	}
+}
@@ -0,0 +1,719 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	collections::HashMap,
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+	sync::OnceLock,
+};
+
+use anyhow::{
+	anyhow,
+	Result,
+};
+use askama::Template;
+use log::{
+	info,
+	warn,
+};
+use serde::Deserialize;
+use sha2::{
+	Digest,
+	Sha256,
+};
+
+use crate::{
+	link,
+	markup::unescape,
+	site,
+	split_url,
+};
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+	<rootfiles>
+		<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+	</rootfiles>
+</container>
+"#;
+
+/// Files in `OEBPS` that are not from the input.
+const RESERVED: &[&str] = &["content.opf", "nav.xhtml"];
+
+/// Chapters are written into this directory, and resources keep their paths
+/// relative to the chapters.
+const CONTENT_DIR: &str = "content";
+
+/// Headings deeper than this are left out of the table of contents.
+const TOC_DEPTH: u8 = 3;
+
+/// The front matter keys of the first page that describe the book.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+	pub book: BookMatter,
+	pub lang: Option<String>,
+}
+
+/// The `book` front matter key of the first page.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct BookMatter {
+	pub title: Option<String>,
+	#[serde(alias = "authors")]
+	pub author: Authors,
+	pub language: Option<String>,
+	pub identifier: Option<String>,
+	pub publisher: Option<String>,
+	pub description: Option<String>,
+	pub date: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(untagged)]
+pub enum Authors {
+	#[default]
+	None,
+	One(String),
+	Many(Vec<String>),
+}
+
+impl Authors {
+	pub fn into_vec(self) -> Vec<String> {
+		match self {
+			Self::None => Vec::new(),
+			Self::One(s) => vec![s],
+			Self::Many(v) => v,
+		}
+	}
+}
+
+/// The metadata of a book.
+pub struct Metadata {
+	pub title: String,
+	pub authors: Vec<String>,
+	pub language: String,
+	/// A unique identifier such as an ISBN or a UUID URN.
+	pub identifier: String,
+	pub publisher: Option<String>,
+	pub description: Option<String>,
+	pub date: Option<String>,
+	/// The last modification time, such as `2024-05-01T08:30:00Z`.
+	pub modified: String,
+}
+
+/// Returns a UUID URN derived from the title and the authors, so that
+/// rebuilding a book keeps its identifier.
+pub fn identifier(title: &str, authors: &[String]) -> String {
+	let mut h = Sha256::new();
+	h.update(title.as_bytes());
+	for a in authors {
+		h.update([0]);
+		h.update(a.as_bytes());
+	}
+	let mut b = h.finalize();
+	// A version 5 (name based), RFC 4122 variant UUID
+	b[6] = (b[6] & 0x0f) | 0x50;
+	b[8] = (b[8] & 0x3f) | 0x80;
+
+	let hex = b[..16]
+		.iter()
+		.map(|x| format!("{x:02x}"))
+		.collect::<String>();
+	format!(
+		"urn:uuid:{}-{}-{}-{}-{}",
+		&hex[..8],
+		&hex[8..12],
+		&hex[12..16],
+		&hex[16..20],
+		&hex[20..]
+	)
+}
+
+pub struct Heading {
+	pub level: u8,
+	pub id: String,
+	pub title: String,
+}
+
+struct Chapter {
+	/// The path relative to `OEBPS`.
+	path: String,
+	title: String,
+	headings: Vec<Heading>,
+	properties: Vec<&'static str>,
+}
+
+struct Resource {
+	/// The path relative to `OEBPS`.
+	path: String,
+	src: PathBuf,
+	data: Vec<u8>,
+}
+
+#[derive(Template)]
+#[template(path = "content.opf", escape = "html")]
+struct Package<'a> {
+	meta: &'a Metadata,
+	items: Vec<Item>,
+	spine: Vec<String>,
+}
+
+struct Item {
+	id: String,
+	href: String,
+	media_type: &'static str,
+	properties: String,
+}
+
+#[derive(Template)]
+#[template(path = "nav.xhtml", escape = "html")]
+struct Nav<'a> {
+	meta: &'a Metadata,
+	toc: Toc,
+}
+
+/// A level of the table of contents.
+#[derive(Template)]
+#[template(path = "toc.xhtml", escape = "html")]
+struct Toc {
+	entries: Vec<TocEntry>,
+}
+
+struct TocEntry {
+	href: String,
+	title: String,
+	children: Option<Toc>,
+}
+
+/// An EPUB 3 book being put together.
+pub struct Book {
+	meta: Metadata,
+	chapters: Vec<Chapter>,
+	/// The contents of the chapters, in the same order.
+	documents: Vec<String>,
+	resources: Vec<Resource>,
+	/// Paths relative to `OEBPS` that are taken, and the files they're from.
+	paths: HashMap<String, Option<PathBuf>>,
+}
+
+impl Book {
+	pub fn new(meta: Metadata) -> Self {
+		Self {
+			meta,
+			chapters: Vec::new(),
+			documents: Vec::new(),
+			resources: Vec::new(),
+			paths: RESERVED.iter().map(|&s| (s.to_owned(), None)).collect(),
+		}
+	}
+
+	/// Returns the path of a chapter, given the path of its output relative
+	/// to the input directory.
+	pub fn chapter_path(out: &Path) -> String {
+		let mut path = String::from(CONTENT_DIR);
+		for c in out.components() {
+			path.push('/');
+			path.push_str(&c.as_os_str().to_string_lossy());
+		}
+		path
+	}
+
+	/// Adds a chapter, and the local images, stylesheets and other resources
+	/// it uses.
+	///
+	/// `out` is the path of the chapter relative to the input directory, and
+	/// `src` its Markdown file.
+	pub fn add_chapter(
+		&mut self,
+		out: &Path,
+		src: &Path,
+		title: String,
+		headings: Vec<Heading>,
+		xhtml: String,
+	) {
+		let path = Self::chapter_path(out);
+		self.paths.insert(path.clone(), Some(src.to_path_buf()));
+
+		let mut properties = Vec::new();
+		if xhtml.contains("<svg") {
+			properties.push("svg");
+		}
+		if xhtml.contains("<script") {
+			properties.push("scripted");
+		}
+
+		let dir = src.parent().unwrap_or(Path::new(""));
+		let mut remote = false;
+		for (tag, url) in references(&xhtml) {
+			if link::is_web(&url) {
+				warn!(
+					"{}: e-book readers may not load the remote resource {}",
+					src.display(),
+					url
+				);
+				remote = true;
+			} else {
+				self.add_resource(&path, dir, src, &url, tag != "img");
+			}
+		}
+		if remote {
+			properties.push("remote-resources");
+		}
+
+		self.chapters.push(Chapter {
+			path,
+			title,
+			headings,
+			properties,
+		});
+		self.documents.push(xhtml);
+	}
+
+	/// Bundles the file `url` points to, relative to the document at `from`
+	/// whose source is in `dir`.
+	fn add_resource(&mut self, from: &str, dir: &Path, src: &Path, url: &str, report: bool) {
+		let (path, _) = split_url(url);
+		if path.is_empty() || path.starts_with('/') || link::has_scheme(path) {
+			return;
+		}
+		let Ok(path) = percent_encoding::percent_decode_str(path).decode_utf8() else {
+			return;
+		};
+
+		let Some(target) = join(from, &path) else {
+			warn!(
+				"{}: not adding {} to the book: it's outside the book's directory",
+				src.display(),
+				url
+			);
+			return;
+		};
+		let file = dir.join(&*path);
+
+		match self.paths.get(&target) {
+			Some(Some(p)) if is_same(p, &file) => return,
+			Some(_) => {
+				warn!(
+					"{}: not adding {} to the book: another file is at the same place",
+					src.display(),
+					url
+				);
+				return;
+			}
+			None => (),
+		}
+
+		let data = match fs::read(&file) {
+			Ok(data) => data,
+			Err(e) => {
+				// Missing images are reported elsewhere
+				if report {
+					warn!(
+						"{}: failed to read {}: {}",
+						src.display(),
+						file.display(),
+						e
+					);
+				}
+				return;
+			}
+		};
+
+		self.paths.insert(target.clone(), Some(file.clone()));
+		if media_type(&target) == "text/css" {
+			let css = String::from_utf8_lossy(&data).into_owned();
+			let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+			for url in css_references(&css) {
+				if !link::is_web(&url) {
+					self.add_resource(&target, &dir, &file, &url, true);
+				}
+			}
+		}
+
+		self.resources.push(Resource {
+			path: target,
+			src: file,
+			data,
+		});
+	}
+
+	/// Writes the book into `path`.
+	pub fn write(self, path: &Path) -> Result<()> {
+		let mut zip = Zip::default();
+		// Must be first and uncompressed
+		zip.add("mimetype", b"application/epub+zip")?;
+		zip.add("META-INF/container.xml", CONTAINER.as_bytes())?;
+
+		let mut items = Vec::with_capacity(self.chapters.len() + self.resources.len());
+		let mut spine = Vec::with_capacity(self.chapters.len());
+		for (i, c) in self.chapters.iter().enumerate() {
+			let id = format!("c{}", i + 1);
+			items.push(Item {
+				id: id.clone(),
+				href: href("content.opf", &c.path),
+				media_type: "application/xhtml+xml",
+				properties: c.properties.join(" "),
+			});
+			spine.push(id);
+		}
+		for (i, r) in self.resources.iter().enumerate() {
+			items.push(Item {
+				id: format!("r{}", i + 1),
+				href: href("content.opf", &r.path),
+				media_type: media_type(&r.path),
+				properties: String::new(),
+			});
+		}
+
+		let package = Package {
+			meta: &self.meta,
+			items,
+			spine,
+		}
+		.render()?;
+		zip.add("OEBPS/content.opf", package.as_bytes())?;
+
+		let nav = Nav {
+			meta: &self.meta,
+			toc: Toc {
+				entries: self
+					.chapters
+					.iter()
+					.map(|c| {
+						let href = href("nav.xhtml", &c.path);
+						// The first heading is usually the title of the chapter
+						let headings = match &c.headings[..] {
+							[first, rest @ ..] if first.title == c.title => rest,
+							all => all,
+						};
+						TocEntry {
+							children: toc(&href, headings),
+							href,
+							title: c.title.clone(),
+						}
+					})
+					.collect(),
+			},
+		}
+		.render()?;
+		zip.add("OEBPS/nav.xhtml", nav.as_bytes())?;
+
+		for (c, doc) in self.chapters.iter().zip(&self.documents) {
+			zip.add(&format!("OEBPS/{}", c.path), doc.as_bytes())?;
+		}
+		for r in &self.resources {
+			info!("{}: {}", path.display(), r.src.display());
+			zip.add(&format!("OEBPS/{}", r.path), &r.data)?;
+		}
+
+		fs::write(path, zip.finish()?)
+			.map_err(|e| anyhow!("failed to write {}: {}", path.display(), e))
+	}
+}
+
+/// Builds the table of contents of a chapter from its headings.
+fn toc(href: &str, headings: &[Heading]) -> Option<Toc> {
+	let headings = headings
+		.iter()
+		.filter(|h| h.level <= TOC_DEPTH && !h.title.is_empty() && !h.id.is_empty())
+		.collect::<Vec<_>>();
+	nest(href, &headings)
+}
+
+fn nest(href: &str, headings: &[&Heading]) -> Option<Toc> {
+	let min = headings.iter().map(|h| h.level).min()?;
+
+	// Each heading of the lowest level starts an entry, with the deeper
+	// headings after it as its children
+	let mut groups = Vec::<(&Heading, Vec<&Heading>)>::new();
+	for &h in headings {
+		match groups.last_mut() {
+			Some((_, children)) if h.level > min => children.push(h),
+			_ => groups.push((h, Vec::new())),
+		}
+	}
+
+	Some(Toc {
+		entries: groups
+			.into_iter()
+			.map(|(h, children)| TocEntry {
+				href: format!("{href}#{}", utf8_fragment(&h.id)),
+				title: h.title.clone(),
+				children: nest(href, &children),
+			})
+			.collect(),
+	})
+}
+
+fn utf8_fragment(id: &str) -> String {
+	percent_encoding::utf8_percent_encode(id, crate::link::HREF).to_string()
+}
+
+/// Returns a URL pointing to `to` from the file `from`, both relative to
+/// `OEBPS`.
+fn href(from: &str, to: &str) -> String {
+	site::relative_url(Path::new(from), Path::new(to))
+}
+
+/// Resolves `rel` relative to the file `from`, returning `None` if it points
+/// outside `OEBPS`.
+fn join(from: &str, rel: &str) -> Option<String> {
+	let mut parts = from.split('/').collect::<Vec<_>>();
+	parts.pop();
+	for s in rel.split(['/', '\\']) {
+		match s {
+			"" | "." => (),
+			".." => {
+				parts.pop()?;
+			}
+			s => parts.push(s),
+		}
+	}
+	(!parts.is_empty()).then(|| parts.join("/"))
+}
+
+fn is_same(a: &Path, b: &Path) -> bool {
+	a == b
+		|| match (fs::canonicalize(a), fs::canonicalize(b)) {
+			(Ok(a), Ok(b)) => a == b,
+			_ => false,
+		}
+}
+
+/// Returns the resources an XHTML document loads, with the names of the
+/// elements referring to them.
+fn references(xhtml: &str) -> Vec<(String, String)> {
+	let mut refs = Vec::new();
+	let mut rest = xhtml;
+
+	while let Some(i) = rest.find('<') {
+		rest = &rest[i + 1..];
+		let end = rest
+			.find(|c: char| c.is_ascii_whitespace() || matches!(c, '>' | '/'))
+			.unwrap_or(rest.len());
+		let tag = rest[..end].to_ascii_lowercase();
+		let close = rest.find('>').unwrap_or(rest.len());
+		let attrs = attributes(&rest[end..close]);
+		let get = |name: &str| {
+			attrs
+				.iter()
+				.find(|(k, _)| k.eq_ignore_ascii_case(name))
+				.map(|(_, v)| v.clone())
+		};
+
+		let url = match tag.as_str() {
+			"link" => {
+				let rel = get("rel").unwrap_or_default().to_ascii_lowercase();
+				if rel
+					.split_ascii_whitespace()
+					.any(|r| r == "stylesheet" || r == "icon")
+				{
+					get("href")
+				} else {
+					None
+				}
+			}
+			"img" | "script" | "source" | "audio" | "video" | "track" | "embed" => get("src"),
+			"image" | "use" => get("href").or_else(|| get("xlink:href")),
+			_ => None,
+		};
+		refs.extend(
+			url.filter(|u| !u.starts_with('#'))
+				.map(|u| (tag.clone(), u)),
+		);
+		if tag == "video" {
+			refs.extend(get("poster").map(|u| (tag.clone(), u)));
+		}
+		rest = &rest[close..];
+	}
+
+	refs
+}
+
+/// Parses quoted attributes, unescaping their values.
+fn attributes(s: &str) -> Vec<(&str, String)> {
+	let mut attrs = Vec::new();
+	let mut rest = s;
+	while let Some(i) = rest.find('=') {
+		let name = rest[..i]
+			.trim()
+			.rsplit(char::is_whitespace)
+			.next()
+			.unwrap_or_default();
+		let v = rest[i + 1..].trim_start();
+		let Some(q @ ('"' | '\'')) = v.chars().next() else {
+			rest = v;
+			continue;
+		};
+		let end = v[1..].find(q).map_or(v.len(), |n| n + 1);
+		attrs.push((name, unescape(&v[1..end])));
+		rest = &v[usize::min(end + 1, v.len())..];
+	}
+	attrs
+}
+
+/// Returns the URLs in the `url()` functions and `@import` rules of a
+/// stylesheet.
+fn css_references(css: &str) -> Vec<String> {
+	let mut refs = Vec::new();
+
+	let mut rest = css;
+	while let Some(i) = rest.find("url(") {
+		rest = &rest[i + 4..];
+		let end = rest.find(')').unwrap_or(rest.len());
+		let url = rest[..end].trim().trim_matches(['"', '\'']);
+		if !url.is_empty() && !url.starts_with("data:") && !url.starts_with('#') {
+			refs.push(url.to_owned());
+		}
+		rest = &rest[end..];
+	}
+
+	let mut rest = css;
+	while let Some(i) = rest.find("@import") {
+		rest = rest[i + 7..].trim_start();
+		if let Some(q @ ('"' | '\'')) = rest.chars().next() {
+			let end = rest[1..].find(q).map_or(rest.len(), |n| n + 1);
+			refs.push(rest[1..end].to_owned());
+		}
+	}
+
+	refs
+}
+
+/// Returns the media type of a file by its extension.
+fn media_type(path: &str) -> &'static str {
+	let ext = path
+		.rsplit_once('.')
+		.map(|(_, ext)| ext.to_ascii_lowercase())
+		.unwrap_or_default();
+	match ext.as_str() {
+		"xhtml" | "html" | "htm" => "application/xhtml+xml",
+		"css" => "text/css",
+		"js" | "mjs" => "application/javascript",
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"svg" => "image/svg+xml",
+		"webp" => "image/webp",
+		"avif" => "image/avif",
+		"ttf" => "font/ttf",
+		"otf" => "font/otf",
+		"woff" => "font/woff",
+		"woff2" => "font/woff2",
+		"mp3" => "audio/mpeg",
+		"m4a" | "mp4a" => "audio/mp4",
+		"ogg" | "opus" => "audio/ogg",
+		"mp4" | "m4v" => "video/mp4",
+		"webm" => "video/webm",
+		"vtt" => "text/vtt",
+		_ => "application/octet-stream",
+	}
+}
+
+const TOO_LARGE: &str = "the book is too large for a ZIP archive without ZIP64";
+
+/// A ZIP archive with uncompressed entries.
+#[derive(Default)]
+struct Zip {
+	buf: Vec<u8>,
+	central: Vec<u8>,
+	entries: u16,
+}
+
+impl Zip {
+	fn add(&mut self, name: &str, data: &[u8]) -> Result<()> {
+		self.entries = self
+			.entries
+			.checked_add(1)
+			.ok_or_else(|| anyhow!("the book has more than {} files", u16::MAX))?;
+		let offset = u32::try_from(self.buf.len()).map_err(|_| anyhow!(TOO_LARGE))?;
+		let size = u32::try_from(data.len()).map_err(|_| anyhow!(TOO_LARGE))?;
+		let name_len = u16::try_from(name.len())
+			.map_err(|_| anyhow!("the file name {name} is too long for a ZIP archive"))?;
+		let crc = crc32(data);
+		// 1980-01-01 00:00, so that builds are reproducible
+		let (time, date) = (0_u16, (1_u16 << 5) | 1);
+		// The names are UTF-8
+		let flags = 1_u16 << 11;
+
+		let header = |buf: &mut Vec<u8>| {
+			buf.extend(flags.to_le_bytes());
+			// Stored
+			buf.extend(0_u16.to_le_bytes());
+			buf.extend(time.to_le_bytes());
+			buf.extend(date.to_le_bytes());
+			buf.extend(crc.to_le_bytes());
+			buf.extend(size.to_le_bytes());
+			buf.extend(size.to_le_bytes());
+			buf.extend(name_len.to_le_bytes());
+			// Extra field length
+			buf.extend(0_u16.to_le_bytes());
+		};
+
+		self.buf.extend(0x04034b50_u32.to_le_bytes());
+		// Version needed to extract: 2.0
+		self.buf.extend(20_u16.to_le_bytes());
+		header(&mut self.buf);
+		self.buf.extend(name.as_bytes());
+		self.buf.extend(data);
+
+		self.central.extend(0x02014b50_u32.to_le_bytes());
+		// Version made by and needed to extract
+		self.central.extend(20_u16.to_le_bytes());
+		self.central.extend(20_u16.to_le_bytes());
+		header(&mut self.central);
+		// Comment length, disk number, internal and external attributes
+		self.central.extend(0_u16.to_le_bytes());
+		self.central.extend(0_u16.to_le_bytes());
+		self.central.extend(0_u16.to_le_bytes());
+		self.central.extend(0_u32.to_le_bytes());
+		self.central.extend(offset.to_le_bytes());
+		self.central.extend(name.as_bytes());
+		Ok(())
+	}
+
+	fn finish(mut self) -> Result<Vec<u8>> {
+		let offset = u32::try_from(self.buf.len()).map_err(|_| anyhow!(TOO_LARGE))?;
+		let size = u32::try_from(self.central.len()).map_err(|_| anyhow!(TOO_LARGE))?;
+		self.buf.append(&mut self.central);
+
+		self.buf.extend(0x06054b50_u32.to_le_bytes());
+		// Disk numbers
+		self.buf.extend(0_u16.to_le_bytes());
+		self.buf.extend(0_u16.to_le_bytes());
+		self.buf.extend(self.entries.to_le_bytes());
+		self.buf.extend(self.entries.to_le_bytes());
+		self.buf.extend(size.to_le_bytes());
+		self.buf.extend(offset.to_le_bytes());
+		// Comment length
+		self.buf.extend(0_u16.to_le_bytes());
+		Ok(self.buf)
+	}
+}
+
+fn crc32(data: &[u8]) -> u32 {
+	static TABLE: OnceLock<[u32; 256]> = OnceLock::new();
+	let table = TABLE.get_or_init(|| {
+		let mut table = [0; 256];
+		for (i, x) in table.iter_mut().enumerate() {
+			let mut c = i as u32;
+			for _ in 0..8 {
+				c = if c & 1 != 0 {
+					0xedb88320 ^ (c >> 1)
+				} else {
+					c >> 1
+				};
+			}
+			*x = c;
+		}
+		table
+	});
+
+	!data.iter().fold(!0_u32, |c, &b| {
+		table[((c ^ u32::from(b)) & 0xff) as usize] ^ (c >> 8)
+	})
+}
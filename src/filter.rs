@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	collections::HashMap,
+	ffi::OsStr,
+	path::{
+		Path,
+		PathBuf,
+	},
+	sync::{
+		Arc,
+		Mutex,
+	},
+};
+
+use anyhow::{
+	anyhow,
+	Result,
+};
+use globset::{
+	GlobBuilder,
+	GlobSet,
+	GlobSetBuilder,
+};
+use ignore::{
+	gitignore::{
+		Gitignore,
+		GitignoreBuilder,
+	},
+	Match,
+};
+use log::warn;
+
+/// The name of the per directory ignore file, using the gitignore syntax.
+const IGNORE_FILE: &str = ".marsignore";
+
+/// Validates a file extension given on the command line, removing the leading
+/// `.` if any.
+pub fn parse_extension(s: &str) -> Result<String, String> {
+	let ext = s.strip_prefix('.').unwrap_or(s);
+	if ext.is_empty() || ext.contains(['/', '\\', '.']) {
+		Err(format!("invalid file extension `{s}`"))
+	} else {
+		Ok(ext.to_owned())
+	}
+}
+
+/// A set of glob patterns.
+///
+/// Patterns without a `/` are matched against the file name, others against
+/// the path relative to the input directory.
+struct Patterns {
+	names: GlobSet,
+	paths: GlobSet,
+}
+
+impl Patterns {
+	fn new(patterns: &[String]) -> Result<Self> {
+		let mut names = GlobSetBuilder::new();
+		let mut paths = GlobSetBuilder::new();
+
+		for p in patterns {
+			let glob = GlobBuilder::new(p.trim_start_matches('/'))
+				.literal_separator(true)
+				.build()
+				.map_err(|e| anyhow!("invalid glob pattern `{p}`: {}", e.kind()))?;
+			if p.contains('/') {
+				paths.add(glob);
+			} else {
+				names.add(glob);
+			}
+		}
+
+		Ok(Self {
+			names: names.build()?,
+			paths: paths.build()?,
+		})
+	}
+
+	fn is_empty(&self) -> bool {
+		self.names.is_empty() && self.paths.is_empty()
+	}
+
+	fn is_match(&self, rel: &Path) -> bool {
+		rel.file_name()
+			.is_some_and(|name| self.names.is_match(name))
+			|| self.paths.is_match(rel)
+	}
+}
+
+/// Decides which files and directories a directory walk skips, and which files
+/// are converted.
+pub struct Filter {
+	extensions: Vec<String>,
+	include: Patterns,
+	exclude: Patterns,
+	gitignore: bool,
+	/// Ignore files by directory.
+	ignores: Mutex<HashMap<PathBuf, Arc<Gitignore>>>,
+}
+
+impl Filter {
+	/// If `gitignore` is set, `.gitignore` files are honored along with
+	/// `.marsignore` files.
+	pub fn new(
+		extensions: &[String],
+		include: &[String],
+		exclude: &[String],
+		gitignore: bool,
+	) -> Result<Self> {
+		Ok(Self {
+			extensions: extensions.to_vec(),
+			include: Patterns::new(include)?,
+			exclude: Patterns::new(exclude)?,
+			gitignore,
+			ignores: Mutex::default(),
+		})
+	}
+
+	/// Reports whether a file is a Markdown file, going by its extension.
+	///
+	/// Extensions are compared case insensitively, so `README.MD` matches `md`.
+	pub fn is_markdown(&self, path: &Path) -> bool {
+		path.extension()
+			.and_then(OsStr::to_str)
+			.is_some_and(|ext| self.extensions.iter().any(|x| x.eq_ignore_ascii_case(ext)))
+	}
+
+	/// Returns the ignore rules defined in `dir`.
+	fn ignore_file(&self, dir: &Path) -> Arc<Gitignore> {
+		let mut ignores = self.ignores.lock().unwrap();
+		if let Some(gi) = ignores.get(dir) {
+			return Arc::clone(gi);
+		}
+
+		let mut builder = GitignoreBuilder::new(dir);
+		let files = [IGNORE_FILE, ".gitignore"];
+		for name in &files[..if self.gitignore { 2 } else { 1 }] {
+			let p = dir.join(name);
+			if p.is_file() {
+				if let Some(e) = builder.add(&p) {
+					warn!("{}: {}", p.display(), e);
+				}
+			}
+		}
+
+		let gi = Arc::new(builder.build().unwrap_or_else(|e| {
+			warn!("{}: {}", dir.display(), e);
+			Gitignore::empty()
+		}));
+		ignores.insert(dir.to_path_buf(), Arc::clone(&gi));
+		gi
+	}
+
+	/// Returns the reason `path` under the input directory `root` should be
+	/// skipped, if it should be.
+	pub fn skip(&self, root: &Path, path: &Path, is_dir: bool) -> Option<&'static str> {
+		let rel = path.strip_prefix(root).ok()?;
+
+		if self.exclude.is_match(rel) {
+			return Some("excluded by --exclude");
+		}
+		if !is_dir && !self.include.is_empty() && !self.include.is_match(rel) {
+			return Some("not matched by --include");
+		}
+
+		// The deepest ignore file with a matching rule decides
+		for dir in path.ancestors().skip(1) {
+			match self.ignore_file(dir).matched(path, is_dir) {
+				Match::None => (),
+				Match::Ignore(_) => return Some("ignored by an ignore file"),
+				Match::Whitelist(_) => return None,
+			}
+			if dir == root {
+				break;
+			}
+		}
+
+		None
+	}
+}
@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	fmt::Write,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use log::warn;
+use percent_encoding::utf8_percent_encode;
+use pulldown_cmark::{
+	Event,
+	Tag,
+	TagEnd,
+};
+
+use crate::{
+	link::{
+		self,
+		escape,
+		HREF,
+	},
+	split_url,
+	RenderOptions,
+};
+
+/// Returns the local file an image URL points to, if it's a relative URL.
+fn local_path(src: &Path, url: &str) -> Option<PathBuf> {
+	let (path, _) = split_url(url);
+	if path.is_empty() || path.starts_with('/') || link::has_scheme(path) {
+		return None;
+	}
+
+	let path = percent_encoding::percent_decode_str(path)
+		.decode_utf8()
+		.ok()?;
+	Some(src.parent().unwrap_or(Path::new("")).join(&*path))
+}
+
+/// Reports the local images of a page that don't exist and, with
+/// `--image-sizes`, reads the dimensions of the others.
+///
+/// Returns the dimensions of every image in the order they appear.
+pub fn inspect(
+	events: &[Event],
+	src: Option<&Path>,
+	ro: &RenderOptions,
+) -> Vec<Option<(usize, usize)>> {
+	let mut sizes = Vec::new();
+
+	for e in events {
+		let Event::Start(Tag::Image { dest_url, .. }) = e else {
+			continue;
+		};
+
+		let Some(path) = src.and_then(|src| local_path(src, dest_url)) else {
+			sizes.push(None);
+			continue;
+		};
+
+		if !path.exists() {
+			warn!(
+				"{}: the image {} does not exist",
+				src.unwrap_or(Path::new("")).display(),
+				dest_url
+			);
+			sizes.push(None);
+		} else if ro.image_sizes {
+			match imagesize::size(&path) {
+				Ok(size) => sizes.push(Some((size.width, size.height))),
+				Err(e) => {
+					warn!("failed to read the size of {}: {}", path.display(), e);
+					sizes.push(None);
+				}
+			}
+		} else {
+			sizes.push(None);
+		}
+	}
+
+	sizes
+}
+
+/// Writes images with their dimensions and `loading="lazy"` if enabled, and
+/// turns paragraphs containing only an image into figures with `--figures`.
+///
+/// `sizes` is the result of [inspect].
+pub fn render(events: &mut Vec<Event>, sizes: &[Option<(usize, usize)>], ro: &RenderOptions) {
+	if !ro.lazy_images && !ro.image_sizes && !ro.figures {
+		return;
+	}
+
+	let mut out = Vec::with_capacity(events.len());
+	let mut sizes = sizes.iter().copied();
+	let mut i = 0;
+
+	while i < events.len() {
+		if !matches!(events[i], Event::Start(Tag::Image { .. })) {
+			out.push(events[i].clone());
+			i += 1;
+			continue;
+		}
+
+		let end = image_end(events, i);
+		let (img, caption) = img(&events[i..=end], sizes.next().flatten(), ro.lazy_images);
+
+		// A paragraph with nothing but an image
+		let standalone = ro.figures
+			&& matches!(out.last(), Some(Event::Start(Tag::Paragraph)))
+			&& matches!(events.get(end + 1), Some(Event::End(TagEnd::Paragraph)));
+
+		if standalone {
+			out.pop();
+			let mut html = format!("<figure>{img}");
+			if !caption.is_empty() {
+				html.push_str("<figcaption>");
+				escape(&mut html, &caption);
+				html.push_str("</figcaption>");
+			}
+			html.push_str("</figure>\n");
+			out.push(Event::Html(html.into()));
+			i = end + 2;
+		} else {
+			out.push(Event::InlineHtml(img.into()));
+			i = end + 1;
+		}
+	}
+
+	*events = out;
+}
+
+/// Returns the index of the end of the image starting at `start`.
+fn image_end(events: &[Event], start: usize) -> usize {
+	let mut depth = 0;
+	for (i, e) in events.iter().enumerate().skip(start) {
+		match e {
+			Event::Start(Tag::Image { .. }) => depth += 1,
+			Event::End(TagEnd::Image) => {
+				depth -= 1;
+				if depth == 0 {
+					return i;
+				}
+			}
+			_ => (),
+		}
+	}
+	events.len() - 1
+}
+
+/// Writes an `<img>` element, returning it with the caption for a figure: the
+/// title, or the alt text if there's no title.
+fn img(events: &[Event], size: Option<(usize, usize)>, lazy: bool) -> (String, String) {
+	let Event::Start(Tag::Image {
+		dest_url, title, ..
+	}) = &events[0]
+	else {
+		unreachable!();
+	};
+
+	// Like pulldown-cmark, use the text of the description as the alt text
+	let mut alt = String::new();
+	for e in &events[1..] {
+		match e {
+			Event::Text(s) | Event::Code(s) | Event::InlineMath(s) | Event::DisplayMath(s) => {
+				alt.push_str(s)
+			}
+			Event::SoftBreak | Event::HardBreak => alt.push(' '),
+			_ => (),
+		}
+	}
+
+	let mut buf = String::from("<img src=\"");
+	escape(&mut buf, &utf8_percent_encode(dest_url, HREF).to_string());
+	buf.push_str("\" alt=\"");
+	escape(&mut buf, &alt);
+	buf.push('"');
+	if !title.is_empty() {
+		buf.push_str(" title=\"");
+		escape(&mut buf, title);
+		buf.push('"');
+	}
+	if let Some((w, h)) = size {
+		let _ = write!(buf, " width=\"{w}\" height=\"{h}\"");
+	}
+	if lazy {
+		buf.push_str(" loading=\"lazy\"");
+	}
+	buf.push_str(" />");
+
+	let caption = if title.is_empty() {
+		alt
+	} else {
+		title.to_string()
+	};
+	(buf, caption)
+}
@@ -0,0 +1,351 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use anyhow::{
+	anyhow,
+	bail,
+	Result,
+};
+use log::warn;
+
+use crate::parse_front_matter;
+
+/// An include directive, such as `{{< include "setup.md" lines="3-10" >}}`.
+pub struct Directive {
+	pub path: String,
+	/// The first and the last line to include, 1-based and inclusive.
+	pub lines: Option<(usize, usize)>,
+	/// Include the file in a fenced code block with this language, if set.
+	pub code: Option<String>,
+}
+
+impl Directive {
+	/// Parses a directive from a line, returning `Ok(None)` if the line is not
+	/// an include directive.
+	pub fn parse(line: &str) -> Result<Option<Self>> {
+		let Some(args) = line
+			.trim()
+			.strip_prefix("{{<")
+			.and_then(|s| s.strip_suffix(">}}"))
+			.map(str::trim)
+			.and_then(|s| s.strip_prefix("include"))
+			.filter(|s| s.starts_with(char::is_whitespace))
+		else {
+			return Ok(None);
+		};
+
+		let mut args = args.trim_start();
+		let path = take_value(&mut args)?.ok_or_else(|| anyhow!("missing the file path"))?;
+
+		let mut dir = Self {
+			path,
+			lines: None,
+			code: None,
+		};
+
+		while !args.is_empty() {
+			let end = args
+				.find(|c: char| c == '=' || c.is_whitespace())
+				.unwrap_or(args.len());
+			let key = &args[..end];
+			args = &args[end..];
+
+			let val = match args.strip_prefix('=') {
+				Some(rest) => {
+					args = rest;
+					Some(take_value(&mut args)?.unwrap_or_default())
+				}
+				None => None,
+			};
+
+			match (key, val) {
+				("lines", Some(val)) => dir.lines = Some(parse_range(&val)?),
+				("code", val) => dir.code = Some(val.unwrap_or_default()),
+				("lines", None) => {
+					bail!("the lines option requires a value such as lines=\"3-10\"")
+				}
+				_ => bail!("unknown option `{key}`"),
+			}
+			args = args.trim_start();
+		}
+
+		Ok(Some(dir))
+	}
+}
+
+/// Takes a quoted or bare value from the start of `s`.
+pub fn take_value(s: &mut &str) -> Result<Option<String>> {
+	if let Some(rest) = s.strip_prefix('"') {
+		let end = rest
+			.find('"')
+			.ok_or_else(|| anyhow!("unterminated string"))?;
+		let val = rest[..end].to_owned();
+		*s = rest[end + 1..].trim_start();
+		Ok(Some(val))
+	} else {
+		let end = s.find(char::is_whitespace).unwrap_or(s.len());
+		let val = &s[..end];
+		*s = s[end..].trim_start();
+		Ok((!val.is_empty()).then(|| val.to_owned()))
+	}
+}
+
+/// Parses `N`, `N-M`, `N-` or `-M`.
+pub fn parse_range(s: &str) -> Result<(usize, usize)> {
+	let parse = |s: &str, default| -> Result<usize> {
+		let s = s.trim();
+		if s.is_empty() {
+			return Ok(default);
+		}
+		match s.parse::<usize>() {
+			Ok(0) | Err(_) => bail!("invalid line range `{s}`: line numbers start at 1"),
+			Ok(n) => Ok(n),
+		}
+	};
+
+	let (start, end) = match s.split_once('-') {
+		Some((a, b)) => (parse(a, 1)?, parse(b, usize::MAX)?),
+		None if s.trim().is_empty() => {
+			bail!("invalid line range: expected a line such as 5 or a range such as 3-10")
+		}
+		None => {
+			let n = parse(s, 0)?;
+			(n, n)
+		}
+	};
+	if start > end {
+		bail!("invalid line range `{s}`: the start is after the end");
+	}
+	Ok((start, end))
+}
+
+/// Tracks fenced code blocks, in which directives are not expanded.
+#[derive(Default)]
+pub struct Fence {
+	open: Option<(char, usize)>,
+}
+
+impl Fence {
+	/// Returns whether `line` is inside a code block or is a fence.
+	pub fn update(&mut self, line: &str) -> bool {
+		let s = line.trim_start_matches(' ');
+		if line.len() - s.len() > 3 {
+			return self.open.is_some();
+		}
+
+		let c = match s.chars().next() {
+			Some(c @ ('`' | '~')) => c,
+			_ => return self.open.is_some(),
+		};
+		let n = s.chars().take_while(|&x| x == c).count();
+		if n < 3 {
+			return self.open.is_some();
+		}
+
+		match self.open {
+			None => self.open = Some((c, n)),
+			Some((open, len)) if open == c && n >= len && s[n..].trim().is_empty() => {
+				self.open = None;
+			}
+			Some(_) => (),
+		}
+		true
+	}
+}
+
+/// Expands the include directives in `source`, the contents of the file at
+/// `path`.
+///
+/// Included paths are relative to the including file. Returns `None` if there
+/// is nothing to expand.
+pub fn expand(source: &str, path: &Path) -> Result<Option<String>> {
+	if !source.contains("{{<") {
+		return Ok(None);
+	}
+
+	let mut stack = Vec::new();
+	if let Ok(p) = fs::canonicalize(path) {
+		stack.push(p);
+	}
+
+	let mut buf = String::with_capacity(source.len());
+	expand_into(&mut buf, source, path, &mut stack, "")?;
+	Ok(Some(buf))
+}
+
+/// Reports the include directives in `source`, the contents of the file at
+/// `path`, without expanding them.
+///
+/// Used with `--sanitize`, since untrusted Markdown could otherwise include
+/// any file that can be read.
+pub fn refuse(source: &str, path: &Path) {
+	if !source.contains("{{<") {
+		return;
+	}
+
+	let mut fence = Fence::default();
+	for (n, line) in source.split_inclusive('\n').enumerate() {
+		if !fence.update(line) && !matches!(Directive::parse(line), Ok(None)) {
+			warn!(
+				"{}:{}: not expanding the include directive: includes are disabled by --sanitize",
+				path.display(),
+				n + 1
+			);
+		}
+	}
+}
+
+fn expand_into(
+	buf: &mut String,
+	source: &str,
+	path: &Path,
+	stack: &mut Vec<PathBuf>,
+	indent: &str,
+) -> Result<()> {
+	let mut fence = Fence::default();
+
+	for (n, line) in source.split_inclusive('\n').enumerate() {
+		if fence.update(line) {
+			push_line(buf, indent, line);
+			continue;
+		}
+
+		let dir = Directive::parse(line).map_err(|e| {
+			anyhow!(
+				"{}:{}: invalid include directive: {}",
+				path.display(),
+				n + 1,
+				e
+			)
+		})?;
+		let Some(dir) = dir else {
+			push_line(buf, indent, line);
+			continue;
+		};
+
+		let target = path.parent().unwrap_or(Path::new("")).join(&dir.path);
+		let canonical = fs::canonicalize(&target).map_err(|e| {
+			anyhow!(
+				"{}:{}: failed to include {}: {}",
+				path.display(),
+				n + 1,
+				target.display(),
+				e
+			)
+		})?;
+
+		if let Some(i) = stack.iter().position(|p| p == &canonical) {
+			let cycle = stack[i..]
+				.iter()
+				.chain([&canonical])
+				.map(|p| p.display().to_string())
+				.collect::<Vec<_>>();
+			bail!(
+				"{}:{}: circular include: {}",
+				path.display(),
+				n + 1,
+				cycle.join(" -> ")
+			);
+		}
+
+		let contents = fs::read_to_string(&target).map_err(|e| {
+			anyhow!(
+				"{}:{}: failed to include {}: {}",
+				path.display(),
+				n + 1,
+				target.display(),
+				e
+			)
+		})?;
+
+		let mut contents = contents.as_str();
+		if dir.code.is_none() {
+			// The front matter of included pages is dropped
+			if let Some((_, body)) = parse_front_matter::<serde_yaml::Value>(contents) {
+				contents = body.trim_start_matches(['\r', '\n']);
+			}
+		}
+
+		if let Some((start, end)) = dir.lines {
+			let total = contents.lines().count();
+			if start > total {
+				bail!(
+					"{}:{}: line {} is out of bounds: {} has {} lines",
+					path.display(),
+					n + 1,
+					start,
+					target.display(),
+					total
+				);
+			}
+			let from = contents
+				.split_inclusive('\n')
+				.take(start - 1)
+				.map(str::len)
+				.sum::<usize>();
+			let to = from
+				+ contents[from..]
+					.split_inclusive('\n')
+					.take(end - start + 1)
+					.map(str::len)
+					.sum::<usize>();
+			contents = &contents[from..to];
+		}
+
+		let line_indent = &line[..line.len() - line.trim_start().len()];
+		let indent = format!("{indent}{line_indent}");
+
+		match &dir.code {
+			Some(lang) => {
+				let lang = if lang.is_empty() {
+					target
+						.extension()
+						.map_or_else(String::new, |s| s.to_string_lossy().into_owned())
+				} else {
+					lang.clone()
+				};
+				// The fence must be longer than any backtick run in the code
+				let mut longest = 0;
+				let mut run = 0;
+				for c in contents.chars() {
+					run = if c == '`' { run + 1 } else { 0 };
+					longest = longest.max(run);
+				}
+				let fence = "`".repeat(usize::max(3, longest + 1));
+
+				push_line(buf, &indent, &format!("{fence}{lang}\n"));
+				for line in contents.split_inclusive('\n') {
+					push_line(buf, &indent, line);
+				}
+				if !buf.ends_with('\n') {
+					buf.push('\n');
+				}
+				push_line(buf, &indent, &format!("{fence}\n"));
+			}
+			None => {
+				stack.push(canonical);
+				expand_into(buf, contents, &target, stack, &indent)?;
+				stack.pop();
+				if !buf.ends_with('\n') {
+					buf.push('\n');
+				}
+			}
+		}
+	}
+
+	Ok(())
+}
+
+fn push_line(buf: &mut String, indent: &str, line: &str) {
+	if !indent.is_empty() && !line.trim().is_empty() {
+		buf.push_str(indent);
+	}
+	buf.push_str(line);
+}
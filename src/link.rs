@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: MIT
+
+use std::fmt::Write;
+
+use percent_encoding::{
+	utf8_percent_encode,
+	AsciiSet,
+	CONTROLS,
+};
+use pulldown_cmark::{
+	CowStr,
+	Event,
+	Tag,
+	TagEnd,
+};
+
+use crate::RenderOptions;
+
+/// Characters percent-encoded in URLs written into HTML attributes.
+pub const HREF: &AsciiSet = &CONTROLS
+	.add(b' ')
+	.add(b'"')
+	.add(b'<')
+	.add(b'>')
+	.add(b'\\')
+	.add(b'^')
+	.add(b'`')
+	.add(b'{')
+	.add(b'|')
+	.add(b'}');
+
+/// An attribute added to external links, given as `name=value`.
+#[derive(Clone, Debug)]
+pub struct LinkAttr {
+	name: String,
+	value: String,
+}
+
+impl LinkAttr {
+	pub fn parse(s: &str) -> Result<Self, String> {
+		let (name, value) = s.split_once('=').unwrap_or((s, ""));
+		let name = name.trim();
+
+		if name.is_empty()
+			|| !name
+				.bytes()
+				.all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b':'))
+		{
+			Err(format!("invalid attribute name `{name}`"))
+		} else if name.eq_ignore_ascii_case("href") {
+			Err("the href attribute can't be changed".into())
+		} else {
+			Ok(Self {
+				name: name.to_ascii_lowercase(),
+				value: value.trim().to_owned(),
+			})
+		}
+	}
+}
+
+/// Reports whether a URL points outside the site.
+///
+/// Only `http`, `https` and protocol-relative URLs are external, unless they
+/// start with `base_url`.
+fn is_external(url: &str, base_url: Option<&str>) -> bool {
+	is_web(url)
+		&& !base_url.is_some_and(|base| {
+			let base = base.trim_end_matches('/');
+			url.strip_prefix(base)
+				.is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?', '#']))
+		})
+}
+
+/// Returns whether `url` is an `http`, `https` or protocol-relative URL.
+pub fn is_web(url: &str) -> bool {
+	url.starts_with("//")
+		|| url.split_once(':').is_some_and(|(scheme, _)| {
+			scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+		})
+}
+
+/// Returns whether `url` starts with a scheme, such as `mailto:`.
+pub fn has_scheme(url: &str) -> bool {
+	url.split(['/', '?', '#'])
+		.next()
+		.is_some_and(|s| s.contains(':'))
+}
+
+/// Adds the attributes and the class configured with `--external-link-attr`
+/// and `--external-link-class` to external links.
+pub struct Decorator<'a> {
+	base_url: Option<&'a str>,
+	class: String,
+	attrs: Vec<&'a LinkAttr>,
+	/// Whether each open link was written by us.
+	stack: Vec<bool>,
+}
+
+impl<'a> Decorator<'a> {
+	/// Returns `None` if there's nothing to add to external links.
+	pub fn new(ro: &'a RenderOptions) -> Option<Self> {
+		let mut classes = ro.external_link_class.clone();
+		let mut attrs = Vec::with_capacity(ro.external_link_attr.len());
+		for a in &ro.external_link_attr {
+			if a.name == "class" {
+				classes.push(a.value.clone());
+			} else {
+				attrs.push(a);
+			}
+		}
+
+		if classes.is_empty() && attrs.is_empty() {
+			return None;
+		}
+
+		Some(Self {
+			base_url: ro.base_url.as_deref(),
+			class: classes.join(" "),
+			attrs,
+			stack: Vec::new(),
+		})
+	}
+
+	pub fn event<'e>(&mut self, e: Event<'e>) -> Event<'e> {
+		match e {
+			Event::Start(Tag::Link {
+				ref dest_url,
+				ref title,
+				..
+			}) => {
+				let external = is_external(dest_url, self.base_url);
+				self.stack.push(external);
+				if !external {
+					return e;
+				}
+
+				let mut buf = String::from("<a href=\"");
+				escape(&mut buf, &utf8_percent_encode(dest_url, HREF).to_string());
+				buf.push('"');
+				if !title.is_empty() {
+					buf.push_str(" title=\"");
+					escape(&mut buf, title);
+					buf.push('"');
+				}
+				if !self.class.is_empty() {
+					buf.push_str(" class=\"");
+					escape(&mut buf, &self.class);
+					buf.push('"');
+				}
+				for a in &self.attrs {
+					let _ = write!(buf, " {}=\"", a.name);
+					escape(&mut buf, &a.value);
+					buf.push('"');
+				}
+				buf.push('>');
+
+				Event::InlineHtml(CowStr::from(buf))
+			}
+			Event::End(TagEnd::Link) if self.stack.pop() == Some(true) => {
+				Event::InlineHtml(CowStr::Borrowed("</a>"))
+			}
+			_ => e,
+		}
+	}
+}
+
+/// Escapes text for use in an attribute value.
+pub fn escape(buf: &mut String, s: &str) {
+	for c in s.chars() {
+		match c {
+			'&' => buf.push_str("&amp;"),
+			'<' => buf.push_str("&lt;"),
+			'>' => buf.push_str("&gt;"),
+			'"' => buf.push_str("&quot;"),
+			'\'' => buf.push_str("&#39;"),
+			_ => buf.push(c),
+		}
+	}
+}
@@ -15,6 +15,8 @@ static LOGGER: OnceLock<Logger> = OnceLock::new();
 #[derive(Debug)]
 struct Logger {
 	level: Level,
+	/// Whether stdout is taken by the output.
+	stdout_busy: bool,
 }
 
 impl Log for Logger {
@@ -31,6 +33,7 @@ impl Log for Logger {
 			match r.level() {
 				Level::Error => eprintln!("error: {}", r.args()),
 				Level::Warn => eprintln!("warning: {}", r.args()),
+				Level::Info if self.stdout_busy => eprintln!("{}", r.args()),
 				Level::Info => println!("{}", r.args()),
 				Level::Debug | Level::Trace => eprintln!("debug: {}", r.args()),
 			}
@@ -40,8 +43,10 @@ impl Log for Logger {
 	fn flush(&self) {}
 }
 
-pub fn init(level: Level) {
-	LOGGER.set(Logger { level }).unwrap();
+/// Sets up logging; informational messages go to stdout unless
+/// `stdout_busy` is set, since the output is written there.
+pub fn init(level: Level, stdout_busy: bool) {
+	LOGGER.set(Logger { level, stdout_busy }).unwrap();
 	log::set_logger(LOGGER.get().unwrap()).unwrap();
 	log::set_max_level(LevelFilter::Debug);
 }
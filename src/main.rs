@@ -2,20 +2,41 @@
 
 #[cfg(feature = "argfile")]
 mod argfile;
+mod csp;
+mod date;
+mod diagram;
+mod epub;
 mod file_name;
+mod filter;
+mod image;
+mod include;
+mod link;
 mod logger;
+mod manifest;
+mod markdown;
+mod markup;
+mod minify;
 mod pretty;
+mod print;
+mod sanitize;
+mod site;
+mod sri;
+mod taxonomy;
 #[cfg(test)]
 mod tests;
+mod xhtml;
 
 use std::{
 	borrow::Cow::{
 		self,
 		Borrowed,
+		Owned,
 	},
 	collections::{
 		btree_map::Entry,
 		BTreeMap,
+		HashMap,
+		HashSet,
 	},
 	ffi::OsString,
 	fs::{
@@ -26,11 +47,13 @@ use std::{
 		self,
 		Read,
 	},
+	mem,
 	path::{
 		Path,
 		PathBuf,
 	},
 	process,
+	sync::Arc,
 };
 
 use anyhow::{
@@ -43,7 +66,9 @@ use clap::Parser as ArgParser;
 use indexmap::IndexSet;
 use jwalk::WalkDir;
 use log::{
+	error,
 	info,
+	warn,
 	Level,
 };
 use normpath::{
@@ -53,16 +78,43 @@ use normpath::{
 use pulldown_cmark::{
 	html,
 	Event,
+	LinkType,
 	Options,
 	Parser,
 	Tag,
+	TagEnd,
 };
 use serde::Deserialize;
-use tidier::FormatOptions;
+use tidier::{
+	Diagnostic,
+	FormatOptions,
+};
 
 use self::{
+	csp::ReferrerPolicy,
+	date::Timestamp,
+	epub::{
+		Book,
+		Heading,
+	},
 	file_name::FileName,
+	filter::Filter,
+	link::LinkAttr,
+	manifest::Manifest,
+	markdown::Extension,
 	pretty::FormatArg,
+	site::{
+		FrontMatter,
+		Page,
+		PageNav,
+		Sidebar,
+		Site,
+	},
+	sri::Resource,
+	taxonomy::{
+		PageTerms,
+		Taxonomy,
+	},
 };
 
 const HELP_FOOTER: &str = "\
@@ -79,6 +131,29 @@ struct Cmd {
 	/// Write all converted html files into a directory
 	#[arg(short = 'O', long, group = "output")]
 	out_dir: Option<PathBuf>,
+	/// Write a directory or the given files into an EPUB 3 book
+	#[arg(
+		long,
+		group = "output",
+		value_name = "FILE",
+		conflicts_with_all = ["minify", "format", "format_error_exit"]
+	)]
+	epub: Option<PathBuf>,
+	/// The title of the book, instead of the one in the front matter of the
+	/// first page
+	#[arg(long, value_name = "TITLE", requires = "epub")]
+	book_title: Option<String>,
+	/// An author of the book, instead of the ones in the front matter of the
+	/// first page
+	#[arg(long, value_name = "NAME", requires = "epub")]
+	book_author: Vec<String>,
+	/// A unique identifier for the book, such as an ISBN; one is made from the
+	/// title and the authors by default
+	#[arg(long, value_name = "ID", requires = "epub")]
+	book_id: Option<String>,
+	/// Render multiple files, in order, into a single page
+	#[arg(long, conflicts_with_all = ["out_dir", "epub"])]
+	concat: bool,
 
 	/// Path to a single directory or one or more markdown files
 	#[arg(required_unless_present = "help_format")]
@@ -87,6 +162,34 @@ struct Cmd {
 	/// Do not ignore hidden files and directories while converting directories
 	#[arg(short, long)]
 	all: bool,
+	/// The extensions of the Markdown files to convert while converting
+	/// directories, matched case insensitively
+	#[arg(
+		long = "ext",
+		value_name = "EXT",
+		value_delimiter = ',',
+		default_value = "md,markdown,mdown,mkd",
+		value_parser = filter::parse_extension,
+	)]
+	extensions: Vec<String>,
+	/// Only convert files matching a glob pattern while converting directories
+	///
+	/// Patterns without a `/` match file names, others match paths relative to
+	/// the input directory.
+	#[arg(long, value_name = "GLOB")]
+	include: Vec<String>,
+	/// Skip files and directories matching a glob pattern while converting
+	/// directories
+	#[arg(long, value_name = "GLOB")]
+	exclude: Vec<String>,
+	/// Honor .gitignore files along with .marsignore files while converting
+	/// directories
+	#[arg(long)]
+	gitignore: bool,
+	/// Remove files written into the output directory by a previous run that
+	/// no longer have a source
+	#[arg(long, requires = "out_dir")]
+	clean: bool,
 
 	#[command(flatten)]
 	opts: RenderOptions,
@@ -100,6 +203,18 @@ struct Cmd {
 	/// Fail on formatting errors instead of writing the unformatted HTML
 	#[arg(long)]
 	format_error_exit: bool,
+	/// Minify the output instead of formatting it: remove comments, collapse
+	/// whitespace and minify inline styles and scripts
+	#[arg(long, conflicts_with_all = ["format", "format_error_exit"])]
+	minify: bool,
+	/// Write well-formed XHTML, with self-closed void elements and the XHTML
+	/// namespace, for XML tools; it's not formatted
+	#[arg(long, conflicts_with_all = ["minify", "format", "format_error_exit"])]
+	xhtml: bool,
+	/// Check every page with tidy, reporting its errors, and exit with an
+	/// error if a page is not valid
+	#[arg(long)]
+	validate: bool,
 
 	/// Display a list of formatting options for use with --format
 	#[arg(long)]
@@ -110,6 +225,23 @@ struct Cmd {
 	verbose: bool,
 }
 
+impl Cmd {
+	/// Turns on the options that others depend on.
+	fn imply(&mut self) {
+		// The table of contents of a book links to the headings
+		if self.epub.is_some() {
+			self.opts.heading_ids = true;
+		}
+		self.opts.wikilinks = self.concat
+			|| self.epub.is_some()
+			|| (self.out_dir.is_some() && self.path.len() == 1 && self.path[0].is_dir());
+		// Wikilinks to sections point to the heading ids
+		if markdown::options(&self.opts, &BTreeMap::new()).contains(Options::ENABLE_WIKILINKS) {
+			self.opts.heading_ids = true;
+		}
+	}
+}
+
 #[derive(ArgParser)]
 struct RenderOptions {
 	/// Set the lang attribute of <html>
@@ -129,6 +261,34 @@ struct RenderOptions {
 	/// Import Sakura.css
 	#[arg(short = 'S', long)]
 	sakura_css: bool,
+	/// Add a print stylesheet with page breaks before top level headings,
+	/// page numbers and the addresses of external links
+	#[arg(long)]
+	print: bool,
+	/// Text at the top of every printed page, unless the page sets
+	/// `print_header`
+	#[arg(long, value_name = "TEXT", requires = "print")]
+	print_header: Option<String>,
+	/// Text at the bottom of every printed page, unless the page sets
+	/// `print_footer`
+	#[arg(long, value_name = "TEXT", requires = "print")]
+	print_footer: Option<String>,
+	/// Remove the raw HTML, attributes and URL schemes that are not known to be
+	/// safe, for rendering untrusted Markdown; the head, css and script
+	/// metadata keys and include directives are ignored
+	#[arg(long)]
+	sanitize: bool,
+	/// Add a Content-Security-Policy meta tag allowing only the stylesheets,
+	/// scripts and images used by each page
+	#[arg(long)]
+	csp: bool,
+	/// Add a referrer policy meta tag
+	#[arg(long, value_name = "POLICY")]
+	referrer: Option<ReferrerPolicy>,
+	/// Add Subresource Integrity hashes to stylesheets and scripts that are
+	/// local files, relative to the Markdown file
+	#[arg(long)]
+	sri: bool,
 
 	/// Append raw HTML into <head>
 	#[arg(long, default_value_t = String::new(), hide_default_value = true)]
@@ -137,14 +297,92 @@ struct RenderOptions {
 	/// Turn newlines into hard breaks
 	#[arg(short = 'H', long)]
 	hard_breaks: bool,
-	/// Do not convert URL's that end with .md (effective only while converting
-	/// a directory)
+	/// Disable every Markdown extension, sticking to CommonMark; extensions can
+	/// be turned back on with --enable
+	#[arg(long)]
+	commonmark: bool,
+	/// Enable a Markdown extension
+	#[arg(long, value_name = "EXT", value_delimiter = ',')]
+	enable: Vec<Extension>,
+	/// Disable a Markdown extension
+	#[arg(long, value_name = "EXT", value_delimiter = ',')]
+	disable: Vec<Extension>,
+	/// Replace emoji shortcodes such as :rocket: with the emoji
+	#[arg(long)]
+	emoji: bool,
+	/// Like --emoji, but wrap the emoji in <span class="emoji"> elements
+	#[arg(long)]
+	emoji_spans: bool,
+	/// Add an attribute to links to other sites, such as `target=_blank` or
+	/// `rel=noopener noreferrer`
+	#[arg(long, value_name = "NAME=VALUE", value_parser = LinkAttr::parse)]
+	external_link_attr: Vec<LinkAttr>,
+	/// Add a CSS class to links to other sites
+	#[arg(long, value_name = "CLASS")]
+	external_link_class: Vec<String>,
+	/// The URL the site is published at; links to it are not considered
+	/// external
+	#[arg(long, value_name = "URL")]
+	base_url: Option<String>,
+	/// Set the width and height of local images from the image files
+	#[arg(long)]
+	image_sizes: bool,
+	/// Make browsers load images only when they're about to be seen
+	#[arg(long)]
+	lazy_images: bool,
+	/// Write paragraphs containing only an image as figures, captioned with
+	/// the image title or description
+	#[arg(long)]
+	figures: bool,
+	/// Add the Mermaid script to pages with Mermaid diagrams
+	#[arg(long)]
+	mermaid: bool,
+	/// Give headings without an id one made from their text, so that they can
+	/// be linked to
+	#[arg(long)]
+	heading_ids: bool,
+	/// Do not convert URL's that point to Markdown files (effective only while
+	/// converting a directory)
 	#[arg(short = 'U', long)]
 	no_convert_urls: bool,
 	/// Convert URL's starting with / as well (root is considered the path
 	/// specified with --out-dir) (effective only while converting a directory)
 	#[arg(long)]
 	convert_base_urls: bool,
+	/// Link each page to the previous and next pages and its parent
+	/// directories (effective only while converting a directory)
+	#[arg(long)]
+	nav: bool,
+	/// Write foo.md as foo/index.html and link to it as foo/ (effective only
+	/// while converting a directory)
+	#[arg(long)]
+	clean_urls: bool,
+	/// Add a navigation tree of the whole directory to each page (effective
+	/// only while converting a directory)
+	#[arg(long)]
+	sidebar: bool,
+	/// Order the navigation by the list of links in this Markdown file
+	/// instead of the directory hierarchy (effective only while converting a
+	/// directory)
+	#[arg(long, value_name = "FILE")]
+	summary: Option<PathBuf>,
+	/// Generate listing pages for the terms of a front matter key, such as
+	/// `tags` (effective only while converting a directory)
+	#[arg(long, value_name = "KEY", value_parser = taxonomy::parse_key)]
+	taxonomy: Vec<String>,
+	/// Also convert drafts and pages that are not yet published or have
+	/// expired (effective only while converting a directory or multiple files)
+	#[arg(long)]
+	drafts: bool,
+	/// Decide whether pages are published based on this date instead of the
+	/// current time
+	#[arg(long, value_name = "DATE", value_parser = Timestamp::parse)]
+	now: Option<Timestamp>,
+
+	/// Whether wikilinks are resolved to pages, which turns the wikilinks
+	/// extension on by default.
+	#[arg(skip)]
+	wikilinks: bool,
 }
 
 #[derive(Template)]
@@ -152,6 +390,31 @@ struct RenderOptions {
 struct Doc<'b, 'o> {
 	md: Metadata<'o>,
 	body: &'b str,
+	csp: Option<String>,
+	referrer: Option<&'static str>,
+	/// The `<style>` element of `--print`.
+	print: Option<String>,
+	nav: Option<PageNav>,
+	sidebar: Option<Sidebar>,
+	terms: Vec<PageTerms>,
+}
+
+/// Per page variables computed by the caller.
+#[derive(Default)]
+struct PageVars {
+	/// The Markdown file, used to find local images.
+	src: Option<PathBuf>,
+	/// The file relative stylesheets and scripts are loaded from, used to
+	/// hash them with `--sri`.
+	out: Option<PathBuf>,
+	/// The title of pages that don't set one in the front matter.
+	title: Option<String>,
+	nav: Option<PageNav>,
+	sidebar: Option<Sidebar>,
+	terms: Vec<PageTerms>,
+	/// How many directories deeper the output is than the source, with
+	/// `--clean-urls`.
+	depth: usize,
 }
 
 #[derive(Default, Deserialize)]
@@ -159,52 +422,152 @@ struct Doc<'b, 'o> {
 struct Metadata<'a> {
 	title: Option<Cow<'a, str>>,
 	lang: Option<Cow<'a, str>>,
-	css: IndexSet<Cow<'a, str>>,
-	script: IndexSet<Cow<'a, str>>,
+	css: IndexSet<Resource<'a>>,
+	script: IndexSet<Resource<'a>>,
 	head: Cow<'a, str>,
+	print_header: Option<Cow<'a, str>>,
+	print_footer: Option<Cow<'a, str>>,
 	hard_breaks: Option<bool>,
+	markdown: BTreeMap<Extension, bool>,
+}
+
+impl<'a> Metadata<'a> {
+	/// Adds the front matter of a page that follows this one in the same
+	/// document.
+	fn merge(&mut self, other: Self) {
+		if self.title.is_none() {
+			self.title = other.title;
+		}
+		if self.lang.is_none() {
+			self.lang = other.lang;
+		}
+		if self.print_header.is_none() {
+			self.print_header = other.print_header;
+		}
+		if self.print_footer.is_none() {
+			self.print_footer = other.print_footer;
+		}
+		self.css.extend(other.css);
+		self.script.extend(other.script);
+		if self.head.is_empty() {
+			self.head = other.head;
+		} else if !other.head.is_empty() && self.head != other.head {
+			self.head = Owned(format!("{}\n{}", self.head, other.head));
+		}
+	}
+}
+
+const WHITESPACE: &[char] = &[' ', '\t', '\n', '\r'];
+
+/// Parses the metadata block on top of `source`, returning it along with the
+/// rest of the document.
+///
+/// Returns `None` if there's no metadata block or it's not valid YAML.
+fn parse_front_matter<'a, T: Deserialize<'a>>(source: &'a str) -> Option<(T, &'a str)> {
+	source
+		.trim_start_matches(WHITESPACE)
+		.strip_prefix("---")
+		.filter(|s| s.starts_with('\n') || s.starts_with("\r\n"))
+		.and_then(|s| {
+			s.split_once("\n---").filter(|(_, body)| {
+				body.starts_with('\n')
+					|| body.starts_with("\r\n")
+					|| body.trim_matches(WHITESPACE).is_empty()
+			})
+		})
+		.and_then(|(md, body)| serde_yaml::from_str::<T>(md).ok().map(|md| (md, body)))
 }
 
 impl<'b, 'a> Doc<'b, 'a> {
-	fn new<F>(html: &'b mut String, source: &str, opts: &'a RenderOptions, map: F) -> Self
+	fn new<F>(
+		html: &'b mut String,
+		source: &'a str,
+		ctx: Context<'a>,
+		vars: PageVars,
+		map: F,
+	) -> Self
 	where
 		F: FnMut(Event) -> Event,
 	{
-		const WHITESPACE: &[char] = &[' ', '\t', '\n', '\r'];
-		let s = source.trim_start_matches(WHITESPACE);
-
-		let (mut md, body) = s
-			.strip_prefix("---")
-			.filter(|s| s.starts_with('\n') || s.starts_with("\r\n"))
-			.and_then(|s| {
-				s.split_once("\n---").filter(|(_, body)| {
-					body.starts_with('\n')
-						|| body.starts_with("\r\n")
-						|| body.trim_matches(WHITESPACE).is_empty()
-				})
-			})
-			.and_then(|(md, body)| {
-				serde_yaml::from_str::<Metadata>(md)
-					.ok()
-					.map(|md| (md, body))
-			})
-			.unwrap_or_else(|| (Metadata::default(), s.trim_matches(WHITESPACE)));
+		let (md, has_mermaid) = Self::render_body(html, source, ctx, vars.src.as_deref(), map);
+		Self::with_body(html, md, has_mermaid, ctx, vars)
+	}
+
+	/// Renders the Markdown in `source` into `html`, returning its front matter
+	/// and whether it has Mermaid diagrams.
+	fn render_body<F>(
+		html: &mut String,
+		source: &'a str,
+		ctx: Context<'a>,
+		src: Option<&Path>,
+		map: F,
+	) -> (Metadata<'a>, bool)
+	where
+		F: FnMut(Event) -> Event,
+	{
+		let opts = ctx.ro;
+		let (mut md, body) = parse_front_matter::<Metadata>(source)
+			.unwrap_or_else(|| (Metadata::default(), source.trim_matches(WHITESPACE)));
+
+		if opts.sanitize {
+			// These keys would let the page add arbitrary HTML, styles or scripts
+			let mut removed = Vec::new();
+			if !md.head.is_empty() {
+				md.head = Borrowed("");
+				removed.push("head");
+			}
+			if !md.css.is_empty() {
+				md.css.clear();
+				removed.push("css");
+			}
+			if !md.script.is_empty() {
+				md.script.clear();
+				removed.push("script");
+			}
+			if !removed.is_empty() {
+				warn!(
+					"{}: ignoring the metadata keys {}",
+					src.map_or_else(|| "<stdin>".into(), |p| p.display().to_string()),
+					removed.join(", ")
+				);
+			}
+		}
 
 		let hard_breaks = md.hard_breaks.unwrap_or(opts.hard_breaks);
 
-		to_html(html, body, hard_breaks, map);
+		let options = markdown::options(opts, &md.markdown);
+		let has_mermaid = to_html(html, body, options, hard_breaks, opts, src, map);
+		if opts.sanitize {
+			*html = sanitize::clean(html);
+		}
+
+		(md, has_mermaid)
+	}
+
+	/// Puts together a page with the rendered `html` and its front matter.
+	fn with_body(
+		html: &'b str,
+		mut md: Metadata<'a>,
+		has_mermaid: bool,
+		ctx: Context<'a>,
+		vars: PageVars,
+	) -> Self {
+		let opts = ctx.ro;
 
 		// Put normalize.css on top
 		if opts.normalize_css {
-			md.css.insert(Borrowed(
-				"https://unpkg.com/normalize.css@8.0.1/normalize.css",
-			));
+			md.css.insert(Resource::builtin(sri::NORMALIZE_CSS));
 			md.css.move_index(md.css.len() - 1, 0);
 		}
 
-		md.css.extend(opts.css.iter().map(|x| Borrowed(x.as_str())));
+		if opts.mermaid && has_mermaid {
+			md.script.insert(Resource::builtin(diagram::MERMAID_SCRIPT));
+		}
+
+		md.css
+			.extend(opts.css.iter().map(|x| Resource::from(x.as_str())));
 		md.script
-			.extend(opts.script.iter().map(|x| Borrowed(x.as_str())));
+			.extend(opts.script.iter().map(|x| Resource::from(x.as_str())));
 
 		if md.head.is_empty() {
 			md.head = Borrowed(opts.head.as_str());
@@ -212,16 +575,74 @@ impl<'b, 'a> Doc<'b, 'a> {
 		if md.lang.is_none() {
 			md.lang = opts.lang.as_deref().map(Borrowed);
 		}
+		if md.title.is_none() {
+			md.title = vars.title.map(Owned);
+		}
 
 		if opts.sakura_css {
-			md.css.insert(Borrowed(
-				"https://cdn.jsdelivr.net/npm/sakura.css/css/sakura.css",
-			));
+			md.css.insert(Resource::builtin(sri::SAKURA_CSS));
+		}
+
+		// Paths in the front matter are relative to the source file, unlike
+		// the ones given on the command line
+		if vars.depth > 0 {
+			for (set, given) in [(&mut md.css, &opts.css), (&mut md.script, &opts.script)] {
+				*set = mem::take(set)
+					.into_iter()
+					.map(|mut r| {
+						if !given.iter().any(|x| *x == r.href) {
+							if let Some(href) = rebase_by(vars.depth, &r.href) {
+								r.href = Owned(href);
+							}
+						}
+						r
+					})
+					.collect();
+			}
+		}
+
+		if opts.sri {
+			md.css = sri::hash_local(md.css, vars.out.as_deref());
+			md.script = sri::hash_local(md.script, vars.out.as_deref());
 		}
 
+		let print = opts.print.then(|| {
+			let css = print::stylesheet(
+				md.print_header.as_deref().or(opts.print_header.as_deref()),
+				md.print_footer.as_deref().or(opts.print_footer.as_deref()),
+			);
+			format!("<style media=\"print\">\n{css}\n</style>")
+		});
+
+		let body = html.trim_matches(WHITESPACE);
+		let csp = opts.csp.then(|| {
+			csp::policy(
+				md.css.iter().map(|x| &*x.href),
+				md.script.iter().map(|x| &*x.href),
+				&[print.as_deref().unwrap_or_default(), &md.head],
+				body,
+				md.script.iter().any(|x| x.href == diagram::MERMAID_SCRIPT),
+				|tag, ty, content| {
+					if ctx.minify {
+						minify::raw_text(tag, ty, content).into_owned()
+					} else if ctx.xhtml {
+						xhtml::raw_text(tag, content).into_owned()
+					} else {
+						content.to_owned()
+					}
+				},
+			)
+		});
+
 		Self {
 			md,
-			body: html.trim_matches(WHITESPACE),
+			body,
+			csp,
+			referrer: opts.referrer.map(ReferrerPolicy::as_str),
+			print,
+			nav: vars.nav,
+			sidebar: vars.sidebar,
+			terms: vars.terms,
 		}
 	}
 }
@@ -231,6 +652,10 @@ struct Context<'a> {
 	ro: &'a RenderOptions,
 	fo: Option<&'a FormatOptions>,
 	format_error_exit: bool,
+	minify: bool,
+	xhtml: bool,
+	validate: bool,
+	clean: bool,
 }
 
 struct Buffer {
@@ -240,6 +665,8 @@ struct Buffer {
 	body: String,
 	// body -> askama template
 	rendered: String,
+	// The number of pages that failed --validate
+	invalid: usize,
 }
 
 impl Buffer {
@@ -249,64 +676,204 @@ impl Buffer {
 			buf: String::new(),
 			body: String::new(),
 			rendered: String::new(),
+			invalid: 0,
 		}
 	}
 
-	fn read_file(&mut self, p: &Path) -> Result<()> {
+	/// Reads a Markdown file, or stdin if `p` is `-`, and expands its include
+	/// directives.
+	fn read_file(&mut self, ctx: Context, p: &Path) -> Result<()> {
 		self.buf.clear();
 
-		let md =
-			fs::metadata(p).map_err(|e| anyhow!("failure reading file {}: {}", p.display(), e))?;
+		if p.as_os_str() == "-" {
+			io::stdin().lock().read_to_string(&mut self.buf)?;
+		} else {
+			let md = fs::metadata(p)
+				.map_err(|e| anyhow!("failure reading file {}: {}", p.display(), e))?;
 
-		self.buf.reserve(usize::min(
-			500 << 20,
-			usize::try_from(md.len()).unwrap_or(usize::MAX),
-		));
+			self.buf.reserve(usize::min(
+				500 << 20,
+				usize::try_from(md.len()).unwrap_or(usize::MAX),
+			));
+
+			File::open(p)
+				.and_then(|mut f| f.read_to_string(&mut self.buf))
+				.map_err(|e| anyhow!("failure reading file {}: {}", p.display(), e))?;
+		}
 
-		File::open(p)
-			.and_then(|mut f| f.read_to_string(&mut self.buf))
-			.map_err(|e| anyhow!("failure reading file {}: {}", p.display(), e))?;
+		// Files included from stdin are relative to the current directory
+		if ctx.ro.sanitize {
+			include::refuse(&self.buf, p);
+		} else if let Some(expanded) = include::expand(&self.buf, p)? {
+			self.buf = expanded;
+		}
 
 		Ok(())
 	}
 
-	fn render<F>(&mut self, ctx: Context, map: F) -> Result<&str>
+	fn render<F>(&mut self, ctx: Context, vars: PageVars, map: F) -> Result<&str>
 	where
 		F: FnMut(Event) -> Event,
 	{
 		self.rendered.clear();
 		self.body.clear();
-		Doc::new(&mut self.body, &self.buf, ctx.ro, map).render_into(&mut self.rendered)?;
+		let name = vars
+			.src
+			.as_deref()
+			.map_or_else(|| "<stdin>".into(), |p| p.display().to_string());
+		Doc::new(&mut self.body, &self.buf, ctx, vars, map).render_into(&mut self.rendered)?;
+		self.finish(ctx, &name)
+	}
+
+	/// Converts, minifies or formats the page in `self.rendered`, as asked.
+	fn finish(&mut self, ctx: Context, name: &str) -> Result<&str> {
+		if ctx.xhtml {
+			self.rendered = xhtml::convert(&self.rendered);
+		}
 
-		if let Some(fo) = ctx.fo {
+		// Whether tidy has already looked at the page
+		let mut checked = false;
+		let html = if ctx.minify {
 			self.buf.clear();
+			minify::html(&self.rendered, &mut self.buf);
+			info!(
+				"{}: minified from {} to {} bytes ({:.1}% smaller)",
+				name,
+				self.rendered.len(),
+				self.buf.len(),
+				100.0 - self.buf.len() as f64 * 100.0 / self.rendered.len().max(1) as f64
+			);
+			&self.buf
+		} else if let Some(fo) = ctx.fo {
+			// A null terminated string saves tidy a copy
 			self.rendered.push('\0');
-			match tidier::format_to(&self.rendered, &mut self.buf, false, fo) {
-				Ok(_) => Ok(&self.buf),
-				Err(e) if ctx.format_error_exit => Err(anyhow!("formatting error: {e}")),
-				Err(_) => Ok(&self.rendered),
+			let res = tidy(&self.rendered, ctx.xhtml, name, |doc| {
+				doc.format_to(&mut self.buf, fo)
+			});
+			self.rendered.pop();
+			checked = true;
+			match res {
+				Ok(()) => &self.buf,
+				Err(e) if ctx.format_error_exit => bail!("{name}: formatting error: {e}"),
+				Err(e) if ctx.validate => {
+					error!("{name}: {e}");
+					self.invalid += 1;
+					&self.rendered
+				}
+				Err(e) => {
+					warn!("{name}: formatting error, writing the unformatted HTML: {e}");
+					&self.rendered
+				}
 			}
 		} else {
-			Ok(&self.rendered)
+			&self.rendered
+		};
+
+		if ctx.validate && !checked {
+			if let Err(e) = tidy(html, ctx.xhtml, name, |_| Ok(())) {
+				error!("{name}: {e}");
+				self.invalid += 1;
+			}
+		}
+
+		Ok(html)
+	}
+
+	/// Fails if a page did not pass `--validate`.
+	fn validated(&self) -> Result<()> {
+		match self.invalid {
+			0 => Ok(()),
+			1 => bail!("1 page is not valid HTML"),
+			n => bail!("{n} pages are not valid HTML"),
 		}
 	}
 }
 
+/// Parses `html` with tidy and runs `f` on the document, logging every
+/// diagnostic as `{name}:{line}:{column}: {message}`.
+///
+/// Returns a summary of what went wrong if the page has errors.
+fn tidy<F>(html: &str, xml: bool, name: &str, f: F) -> Result<(), String>
+where
+	F: FnOnce(&tidier::Doc) -> tidier::Result<()>,
+{
+	fn report(name: &str, diagnostics: &[Diagnostic]) {
+		for d in diagnostics {
+			if d.is_error() {
+				error!("{}:{}:{}: {}", name, d.line, d.column, d.message);
+			} else {
+				warn!("{}:{}:{}: {}", name, d.line, d.column, d.message);
+			}
+		}
+	}
+
+	// `skip` is the number of diagnostics that were already reported
+	let describe = |e: tidier::Error, skip: usize| match e {
+		tidier::Error::Doc(diagnostics) => {
+			report(name, diagnostics.get(skip..).unwrap_or_default());
+			match diagnostics.iter().filter(|d| d.is_error()).count() {
+				1 => String::from("tidy found 1 error"),
+				n => format!("tidy found {n} errors"),
+			}
+		}
+		e => e.to_string(),
+	};
+
+	let doc = tidier::Doc::new(html, xml).map_err(|e| describe(e, 0))?;
+	let diagnostics = doc.diagnostics();
+	report(name, &diagnostics);
+	f(&doc).map_err(|e| describe(e, diagnostics.len()))
+}
+
 #[cfg(windows)]
 fn is_illegal_filepath(s: &str) -> bool {
 	s.bytes()
 		.any(|b| b <= 31 || matches!(b, b'"' | b'<' | b'>' | b'|' | b':' | b'*' | b'?' | b'\\'))
 }
 
-fn to_html<F>(buf: &mut String, md: &str, hard_breaks: bool, mut map: F)
+fn to_html<F>(
+	buf: &mut String,
+	md: &str,
+	options: Options,
+	hard_breaks: bool,
+	opts: &RenderOptions,
+	src: Option<&Path>,
+	mut map: F,
+) -> bool
 where
 	F: FnMut(Event) -> Event,
 {
-	let parser = Parser::new_ext(md, Options::all()).map(|e| match e {
-		Event::SoftBreak if hard_breaks => Event::HardBreak,
-		other => map(other),
+	let mut events = Parser::new_ext(md, options).collect::<Vec<_>>();
+	if opts.heading_ids {
+		markdown::heading_ids(&mut events);
+	}
+	if opts.emoji || opts.emoji_spans {
+		markdown::emoji(&mut events, opts.emoji_spans);
+	}
+	if opts.sanitize {
+		sanitize::report(&events, src);
+	}
+	// Don't run dot on untrusted input
+	let has_mermaid = diagram::render(&mut events, !opts.sanitize);
+	// Image paths must be read before `map` rewrites them
+	let sizes = image::inspect(&events, src, opts);
+
+	let mut events = events
+		.into_iter()
+		.map(|e| match e {
+			Event::SoftBreak if hard_breaks => Event::HardBreak,
+			other => map(other),
+		})
+		.collect::<Vec<_>>();
+	image::render(&mut events, &sizes, opts);
+
+	let mut links = link::Decorator::new(opts);
+	let parser = events.into_iter().map(|e| match &mut links {
+		Some(links) => links.event(e),
+		None => e,
 	});
 	html::push_html(buf, parser);
+	has_mermaid
 }
 
 /// Splits the given url at a query or fragment, returning the slice before and
@@ -323,35 +890,155 @@ fn split_url(url: &str) -> (&str, &str) {
 	}
 }
 
-/// `root` must be normalized.
-fn is_in_dir(root: &BasePath, file_path: &Path, url: &str) -> bool {
+/// Resolves a URL found in the file `file_path` to a normalized path.
+///
+/// URLs starting with `/` are resolved relative to `root`; they are not
+/// resolved if `root` is `None`.
+fn resolve_link(root: Option<&BasePath>, file_path: &Path, url: &str) -> Option<BasePathBuf> {
 	#[cfg(not(windows))]
 	if url.contains(':') {
-		return false;
+		return None;
 	}
-	
-	
+
 	#[cfg(windows)]
 	if is_illegal_filepath(url) {
-		return false;
+		return None;
 	}
 
-	let Ok(file_path) = BasePath::new(file_path) else {
-		return false;
+	let file_path = BasePath::new(file_path).ok()?;
+	let parent = file_path.parent().ok()??;
+
+	let url = percent_encoding::percent_decode_str(url)
+		.decode_utf8()
+		.ok()?;
+	let target = match url.strip_prefix('/') {
+		Some(rest) => root?.join(rest),
+		None => parent.join(&*url),
 	};
-	let Ok(Some(parent)) = file_path.parent() else {
-		return false;
+
+	target.normalize().ok()
+}
+
+/// Rewrites a URL in a page of a directory build, returning `None` if it's
+/// unchanged.
+///
+/// If `convert_pages` is set, links to other pages are pointed to their output
+/// files. Other relative URLs are adjusted for pages written deeper than their
+/// source. `root` must be normalized; without it, links starting with `/` are
+/// not converted.
+fn rewrite_url(
+	site: &Site,
+	root: Option<&BasePath>,
+	page: usize,
+	url: &str,
+	convert_pages: bool,
+) -> Option<String> {
+	if convert_pages {
+		let (path, rest) = split_url(url);
+		let target = resolve_link(root, &site.pages[page].src, path)
+			.filter(|target| root.is_none_or(|root| target.starts_with(root)))
+			.and_then(|target| site.find(target.as_path()));
+
+		if let Some(target) = target {
+			return Some(if path.starts_with('/') {
+				format!("{}{rest}", site.root_url(target))
+			} else {
+				format!("{}{rest}", site.url(page, target))
+			});
+		}
+	}
+
+	rebase_url(site, page, url)
+}
+
+/// Resolves the target of a wikilink, such as `Page Name` or
+/// `Page Name#Section`, to a URL, reporting targets that match no page or more
+/// than one.
+fn resolve_wikilink(site: &Site, page: usize, target: &str) -> Option<String> {
+	let (name, section) = match target.split_once('#') {
+		Some((name, section)) => (name, Some(section)),
+		None => (target, None),
 	};
+	let anchor = section.map_or_else(String::new, |s| format!("#{}", site::slug(s)));
+
+	if name.trim().is_empty() {
+		return Some(anchor);
+	}
+
+	match site.find_by_name(name) {
+		Ok(to) => Some(format!("{}{anchor}", site.url(page, to))),
+		Err(e) => {
+			warn!(
+				"{}: failed to resolve the link [[{}]]: {}",
+				site.pages[page].src.display(),
+				target,
+				e
+			);
+			None
+		}
+	}
+}
+
+/// Prefixes a relative URL with `../` for every directory the output of a page
+/// is deeper than its source, returning `None` if it's unchanged.
+fn rebase_url(site: &Site, page: usize, url: &str) -> Option<String> {
+	rebase_by(site.pages[page].depth_change(), url)
+}
+
+/// Prefixes a relative URL with `../` `depth` times, returning `None` if it's
+/// unchanged.
+fn rebase_by(depth: usize, url: &str) -> Option<String> {
+	if depth == 0 || url.is_empty() || url.starts_with(['/', '#', '?']) || link::has_scheme(url) {
+		None
+	} else {
+		Some(format!("{}{url}", "../".repeat(depth)))
+	}
+}
+
+/// Warns if `url` points to a page that was skipped for not being published.
+fn check_unpublished_link(
+	skipped: &HashSet<PathBuf>,
+	root: Option<&BasePath>,
+	file_path: &Path,
+	url: &str,
+) {
+	if skipped.is_empty() {
+		return;
+	}
+
+	let (url, _) = split_url(url);
+	if resolve_link(root, file_path, url).is_some_and(|target| skipped.contains(target.as_path())) {
+		warn!(
+			"{}: links to the unpublished page {}",
+			file_path.display(),
+			url
+		);
+	}
+}
 
-	let url = percent_encoding::percent_decode_str(url).decode_utf8().unwrap();
-	let target = url
-		.strip_prefix('/')
-		.map(|rest| root.join(rest))
-		.unwrap_or_else(|| parent.join(&*url));
+/// Returns whether the page at `path` is skipped for not being published,
+/// reporting why. Nothing is skipped with `--drafts`.
+fn unpublished(path: &Path, fm: &FrontMatter, ctx: Context) -> Result<bool> {
+	if ctx.ro.drafts {
+		return Ok(false);
+	}
 
-	target
-		.normalize()
-		.is_ok_and(|target| target.starts_with(root) && target.is_file())
+	let now = ctx.ro.now.unwrap_or_else(Timestamp::now);
+	match fm
+		.unpublished(now)
+		.map_err(|e| anyhow!("{}: {}", path.display(), e))?
+	{
+		Some(why) => {
+			info!("skipping {}: {}", path.display(), why);
+			Ok(true)
+		}
+		None => Ok(false),
+	}
+}
+
+/// Returns the front matter keys of `source` that affect the layout.
+fn front_matter(source: &str) -> (FrontMatter, &str) {
+	parse_front_matter::<FrontMatter>(source).unwrap_or_else(|| (FrontMatter::default(), source))
 }
 
 fn has_hidden(url: &str) -> bool {
@@ -370,16 +1057,24 @@ fn run() -> Result<()> {
 	}
 
 	let args = get_args();
-	let c = Cmd::parse_from(args);
+	let mut c = Cmd::parse_from(args);
 
 	if c.help_format {
 		pretty::show_help();
 		return Ok(());
 	}
 
-	logger::init(if c.verbose { Level::Info } else { Level::Warn });
+	let to_stdout = c.epub.is_none()
+		&& c.out_dir.is_none()
+		&& c.out.as_ref().is_none_or(|p| p.as_os_str() == "-");
+	logger::init(if c.verbose { Level::Info } else { Level::Warn }, to_stdout);
+
+	c.imply();
 
-	let fo = (!c.no_format).then(|| {
+	// Tidy's XML mode may re-indent the contents of <pre>, so XHTML is written
+	// as is
+	let xhtml = c.xhtml || c.epub.is_some();
+	let fo = (!c.no_format && !c.minify && !xhtml).then(|| {
 		let mut fo = FormatOptions::new();
 		for o in &c.format {
 			o.apply(&mut fo);
@@ -391,37 +1086,46 @@ fn run() -> Result<()> {
 		fo: fo.as_ref(),
 		ro: &c.opts,
 		format_error_exit: c.format_error_exit,
+		minify: c.minify,
+		xhtml,
+		validate: c.validate,
+		clean: c.clean,
 	};
 
-	if let Some(dir) = &c.out_dir {
+	if let Some(file) = &c.epub {
+		let filter = Filter::new(&c.extensions, &c.include, &c.exclude, c.gitignore)?;
+		let book = BookArgs {
+			title: c.book_title.clone(),
+			authors: c.book_author.clone(),
+			identifier: c.book_id.clone(),
+		};
+		convert_epub(file, &c.path, !c.all, filter, book, ctx)
+	} else if let Some(dir) = &c.out_dir {
 		if c.path.len() == 1 && c.path[0].is_dir() {
-			convert_dir(dir, &c.path[0], !c.all, ctx)
+			let filter = Filter::new(&c.extensions, &c.include, &c.exclude, c.gitignore)?;
+			convert_dir(dir, &c.path[0], !c.all, filter, ctx)
 		} else {
 			convert_all(dir, &c.path, ctx)
 		}
+	} else if c.concat {
+		convert_concat(&c.path, c.out.as_deref(), ctx)
 	} else if c.path.len() != 1 {
-		bail!("cannot write multiple files into one; use the --out-dir option instead");
+		bail!("cannot write multiple files into one; use the --out-dir or --concat option instead");
 	} else {
-		let data = if c.path[0].as_os_str() == "-" {
-			let mut buf = String::with_capacity(8 << 10);
-			io::stdin().lock().read_to_string(&mut buf)?;
-			buf
-		} else {
-			fs::read_to_string(&c.path[0])?
-		};
+		let mut buf = Buffer::new();
+		buf.read_file(ctx, &c.path[0])?;
 
-		let mut buf = Buffer {
-			rendered: String::with_capacity(usize::max(data.len(), 4 << 10)),
-			body: String::with_capacity(usize::max(data.len(), 4 << 10)),
-			buf: data,
+		let vars = PageVars {
+			src: Some(c.path[0].clone()),
+			out: c.out.clone().filter(|p| p.as_os_str() != "-"),
+			..PageVars::default()
 		};
-
-		let html = buf.render(ctx, |x| x)?;
+		let html = buf.render(ctx, vars, |x| x)?;
 		match c.out.as_ref() {
 			Some(p) if p.as_os_str() != "-" => fs::write(p, html)?,
 			_ => print!("{html}"),
 		}
-		Ok(())
+		buf.validated()
 	}
 }
 
@@ -449,88 +1153,758 @@ fn convert_all(dir: &Path, files: &[PathBuf], ctx: Context) -> Result<()> {
 	fs::create_dir_all(dir)?;
 	let mut buf = Buffer::new();
 
+	let mut manifest = Manifest::default();
+	let mut skipped = HashSet::new();
+	if !ctx.ro.drafts {
+		for p in names.values() {
+			buf.read_file(ctx, p.as_path())?;
+			if unpublished(p.as_path(), &front_matter(&buf.buf).0, ctx)? {
+				skipped.insert(p.normalize()?.into_path_buf());
+			}
+		}
+		names.retain(|_, p| {
+			p.normalize()
+				.map_or(true, |p| !skipped.contains(p.as_path()))
+		});
+	}
+
 	for (name, p) in &names {
 		let p = p.as_path();
 		let mut out = dir.join(name.name());
 		out.set_extension("html");
 
-		buf.read_file(p)?;
-		let html = buf.render(ctx, |x| x)?;
+		buf.read_file(ctx, p)?;
+		let vars = PageVars {
+			src: Some(p.to_path_buf()),
+			out: Some(out.clone()),
+			..PageVars::default()
+		};
+		let html = buf.render(ctx, vars, |event| {
+			if let Event::Start(Tag::Link { dest_url, .. }) = &event {
+				check_unpublished_link(&skipped, None, p, dest_url);
+			}
+			event
+		})?;
 		fs::write(&out, html).map_err(|e| anyhow!("rendering to {} failed: {}", p.display(), e))?;
 
 		info!("{}", out.display());
+		manifest.add(dir, &out);
+	}
+
+	if ctx.clean {
+		manifest.clean(dir)?;
 	}
 
+	buf.validated()
+}
+
+/// Returns an error if two pages would be written to the same file in `out`,
+/// such as `a.md` and `a.markdown`.
+fn check_outputs(pages: &[Page], out: &Path) -> Result<()> {
+	let mut outputs = HashMap::with_capacity(pages.len());
+	for page in pages {
+		if let Some(other) = outputs.insert(&page.out, &page.src) {
+			bail!(
+				"{} and {} would both be written to {}",
+				other.display(),
+				page.src.display(),
+				out.join(&page.out).display()
+			);
+		}
+	}
 	Ok(())
 }
 
-fn convert_dir(out: &Path, dir: &Path, skip_hidden: bool, ctx: Context) -> Result<()> {
+fn convert_dir(
+	out: &Path,
+	dir: &Path,
+	skip_hidden: bool,
+	filter: Filter,
+	ctx: Context,
+) -> Result<()> {
 	fs::create_dir_all(out)?;
 	let dir = BasePathBuf::new(dir)?;
+	let root = dir.normalize()?;
+	let filter = Arc::new(filter);
 
 	let mut buf = Buffer::new();
+	let mut manifest = Manifest::default();
+
+	let (pages, skipped) = collect_pages(&dir, skip_hidden, &filter, ctx, &mut buf)?;
+	check_outputs(&pages, out)?;
+
+	let site = layout(pages, &dir, &root, ctx.ro.clean_urls, ctx, &mut buf)?;
+
+	let taxonomies = ctx
+		.ro
+		.taxonomy
+		.iter()
+		.enumerate()
+		.map(|(n, key)| Taxonomy::collect(key, n, &site.pages, ctx.ro.clean_urls))
+		.collect::<Vec<_>>();
+
+	for (i, page) in site.pages.iter().enumerate() {
+		let p = &page.src;
+		let to = out.join(&page.out);
+
+		buf.read_file(ctx, p)?;
+
+		if let Some(parent) = to.parent() {
+			fs::create_dir_all(parent)
+				.map_err(|e| anyhow!("failed to create directory {}: {}", parent.display(), e))?;
+		}
+
+		let vars = PageVars {
+			src: Some(page.src.clone()),
+			out: Some(to.clone()),
+			title: None,
+			nav: ctx.ro.nav.then(|| site.nav(i)),
+			sidebar: ctx.ro.sidebar.then(|| site.sidebar(i)),
+			terms: taxonomies
+				.iter()
+				.enumerate()
+				.map(|(n, t)| PageTerms {
+					key: t.key.clone(),
+					terms: t.links(n, page),
+				})
+				.filter(|t| !t.terms.is_empty())
+				.collect(),
+			depth: page.depth_change(),
+		};
+
+		let html = buf.render(ctx, vars, |event| {
+			rewrite_event(&site, Some(&root), i, &skipped, skip_hidden, ctx.ro, event)
+		})?;
+
+		fs::write(&to, html).map_err(|e| anyhow!("error rendering to {}: {}", to.display(), e))?;
+		info!("{}", to.display());
+		manifest.add(out, &to);
+	}
+
+	for t in &taxonomies {
+		for (path, md) in t.generate(&site.pages) {
+			if let Some(p) = site.pages.iter().find(|p| p.out == path) {
+				warn!(
+					"not generating {}: it would overwrite the output of {}",
+					path.display(),
+					p.src.display()
+				);
+				continue;
+			}
+
+			let to = out.join(&path);
+			if let Some(parent) = to.parent() {
+				fs::create_dir_all(parent).map_err(|e| {
+					anyhow!("failed to create directory {}: {}", parent.display(), e)
+				})?;
+			}
+
+			buf.buf = md;
+			let html = buf.render(ctx, PageVars::default(), |x| x)?;
+			fs::write(&to, html)
+				.map_err(|e| anyhow!("error rendering to {}: {}", to.display(), e))?;
+			info!("{}", to.display());
+			manifest.add(out, &to);
+		}
+	}
+
+	if ctx.clean {
+		manifest.clean(out)?;
+	}
 
-	for entry in WalkDir::new(&dir)
+	buf.validated()
+}
+
+/// Collects the Markdown files in `dir` along with their front matter,
+/// returning them with the normalized paths of the unpublished pages.
+fn collect_pages(
+	dir: &BasePath,
+	skip_hidden: bool,
+	filter: &Arc<Filter>,
+	ctx: Context,
+	buf: &mut Buffer,
+) -> Result<(Vec<Page>, HashSet<PathBuf>)> {
+	let mut files = Vec::new();
+	for entry in WalkDir::new(dir)
 		.skip_hidden(skip_hidden)
+		.process_read_dir({
+			let root = dir.as_path().to_path_buf();
+			let filter = Arc::clone(filter);
+			move |_, _, _, children| {
+				children.retain(|x| {
+					let Ok(x) = x else { return true };
+					let p = x.path();
+					match filter.skip(&root, &p, x.file_type.is_dir()) {
+						Some(why) => {
+							info!("skipping {}: {}", p.display(), why);
+							false
+						}
+						None => true,
+					}
+				});
+			}
+		})
 		.into_iter()
 		.flatten()
-		.filter(|x| x.file_type.is_file() && x.file_name.as_encoded_bytes().ends_with(b".md"))
+		.filter(|x| x.file_type.is_file() && filter.is_markdown(Path::new(&x.file_name)))
 	{
 		let p = entry.path();
+		let rel = p
+			.strip_prefix(dir)
+			.map_err(|e| anyhow!("error constructing target path for {}: {}", p.display(), e))?
+			.to_path_buf();
+		files.push((p, rel));
+	}
 
-		let mut to =
-			out.join(p.strip_prefix(&dir).map_err(|e| {
-				anyhow!("error constructing target path for {}: {}", p.display(), e)
-			})?);
-		to.set_extension("html");
-
-		buf.read_file(&p)?;
+	read_pages(files, ctx, buf)
+}
 
-		if let Some(parent) = to.parent() {
-			fs::create_dir_all(parent)
-				.map_err(|e| anyhow!("failed to create directory {}: {}", parent.display(), e))?;
+/// Reads the given files and their paths relative to the input directory into
+/// pages, returning them with the normalized paths of the unpublished pages.
+fn read_pages(
+	files: Vec<(PathBuf, PathBuf)>,
+	ctx: Context,
+	buf: &mut Buffer,
+) -> Result<(Vec<Page>, HashSet<PathBuf>)> {
+	let mut skipped = HashSet::new();
+	let mut pages = Vec::with_capacity(files.len());
+	for (p, rel) in files {
+		buf.read_file(ctx, &p)?;
+		let (fm, body) = front_matter(&buf.buf);
+		if unpublished(&p, &fm, ctx)? {
+			skipped.insert(BasePathBuf::new(&p)?.normalize()?.into_path_buf());
+			continue;
 		}
+		let options = parse_front_matter::<Metadata>(&buf.buf)
+			.map_or_else(BTreeMap::new, |(md, _)| md.markdown);
+		let options = markdown::options(ctx.ro, &options);
+		pages.push(Page::new(p, rel, fm, body, options, ctx.ro));
+	}
+
+	Ok((pages, skipped))
+}
+
+/// Lays out the pages of `dir` by the summary file, if there's one, or the
+/// directory hierarchy.
+fn layout(
+	pages: Vec<Page>,
+	dir: &BasePath,
+	root: &BasePath,
+	clean_urls: bool,
+	ctx: Context,
+	buf: &mut Buffer,
+) -> Result<Site> {
+	let Some(summary) = &ctx.ro.summary else {
+		return Ok(Site::new(pages, clean_urls));
+	};
+
+	let path = BasePathBuf::new(summary)
+		.and_then(|p| p.normalize())
+		.map_err(|e| {
+			anyhow!(
+				"failed to canonicalize the path {}: {}",
+				summary.display(),
+				e
+			)
+		})?;
+	let rel = path.as_path().strip_prefix(root).map_err(|_| {
+		anyhow!(
+			"the summary file must be inside {}",
+			dir.as_path().display()
+		)
+	})?;
+
+	buf.read_file(ctx, summary)?;
+	Ok(Site::with_summary(pages, rel, &buf.buf, clean_urls))
+}
+
+/// Points the links and images of a page of `site` to the output files.
+///
+/// `root` is the normalized input directory; without it, links starting with
+/// `/` are left alone.
+fn rewrite_event<'e>(
+	site: &Site,
+	root: Option<&BasePath>,
+	i: usize,
+	skipped: &HashSet<PathBuf>,
+	skip_hidden: bool,
+	ro: &RenderOptions,
+	event: Event<'e>,
+) -> Event<'e> {
+	let p = &site.pages[i].src;
+	match event {
+		Event::Start(Tag::Link {
+			link_type: link_type @ LinkType::WikiLink { .. },
+			dest_url,
+			title,
+			id,
+		}) => Event::Start(Tag::Link {
+			link_type,
+			dest_url: resolve_wikilink(site, i, &dest_url).map_or(dest_url, Into::into),
+			title,
+			id,
+		}),
+		Event::Start(Tag::Link {
+			link_type,
+			dest_url,
+			title,
+			id,
+		}) => {
+			check_unpublished_link(skipped, root, p, &dest_url);
+			let skip = ro.no_convert_urls
+				|| (!ro.convert_base_urls && dest_url.starts_with('/'))
+				|| (skip_hidden && has_hidden(split_url(&dest_url).0));
 
-		let html = buf.render(ctx, |event| match event {
-			_ if ctx.ro.no_convert_urls => event,
 			Event::Start(Tag::Link {
 				link_type,
-				dest_url,
+				dest_url: rewrite_url(site, root, i, &dest_url, !skip).map_or(dest_url, Into::into),
 				title,
 				id,
-			}) if ctx.ro.convert_base_urls || !dest_url.starts_with('/') => {
-				let (url, rest) = split_url(&dest_url);
-				if (skip_hidden && has_hidden(url)) || !is_in_dir(&dir, &p, url) {
-					return Event::Start(Tag::Link {
-						link_type,
-						dest_url,
-						title,
-						id,
+			})
+		}
+		Event::Start(Tag::Image {
+			link_type,
+			dest_url,
+			title,
+			id,
+		}) => Event::Start(Tag::Image {
+			link_type,
+			dest_url: rebase_url(site, i, &dest_url).map_or(dest_url, Into::into),
+			title,
+			id,
+		}),
+		_ => event,
+	}
+}
+
+/// Book metadata given on the command line.
+struct BookArgs {
+	title: Option<String>,
+	authors: Vec<String>,
+	identifier: Option<String>,
+}
+
+fn convert_epub(
+	file: &Path,
+	paths: &[PathBuf],
+	skip_hidden: bool,
+	filter: Filter,
+	args: BookArgs,
+	ctx: Context,
+) -> Result<()> {
+	let mut buf = Buffer::new();
+
+	// Chapters link to each other by their XHTML files
+	let xhtml = |mut pages: Vec<Page>| {
+		for p in &mut pages {
+			p.out = p.rel.with_extension("xhtml");
+		}
+		check_outputs(&pages, Path::new("")).map(|_| pages)
+	};
+
+	let (site, root, skipped) = if paths.len() == 1 && paths[0].is_dir() {
+		let dir = BasePathBuf::new(&paths[0])?;
+		let root = dir.normalize()?;
+		let (pages, skipped) = collect_pages(&dir, skip_hidden, &Arc::new(filter), ctx, &mut buf)?;
+		let site = layout(xhtml(pages)?, &dir, &root, false, ctx, &mut buf)?;
+		(site, Some(root), skipped)
+	} else {
+		let mut names = BTreeMap::new();
+		let mut files = Vec::with_capacity(paths.len());
+		for p in paths {
+			let name = FileName::new(p)?;
+			if let Some(other) = names.insert(name, p) {
+				bail!(
+					"duplicate file names:\n- {}\n- {}",
+					other.display(),
+					p.display()
+				);
+			}
+			files.push((p.clone(), PathBuf::from(p.file_name().unwrap_or_default())));
+		}
+
+		let (pages, skipped) = read_pages(files, ctx, &mut buf)?;
+		(Site::new(xhtml(pages)?, false), None, skipped)
+	};
+
+	let order = site.reading_order();
+	let Some(&first) = order.first() else {
+		bail!("there are no pages to put in the book");
+	};
+
+	buf.read_file(ctx, &site.pages[first].src)?;
+	let fm = parse_front_matter::<epub::FrontMatter>(&buf.buf)
+		.map(|(fm, _)| fm)
+		.unwrap_or_default();
+	let book = fm.book;
+
+	let title = args
+		.title
+		.or(book.title)
+		.unwrap_or_else(|| site.pages[first].title.clone());
+	let authors = if args.authors.is_empty() {
+		book.author.into_vec()
+	} else {
+		args.authors
+	};
+	let identifier = args
+		.identifier
+		.or(book.identifier)
+		.unwrap_or_else(|| epub::identifier(&title, &authors));
+	let language = book
+		.language
+		.or(fm.lang)
+		.or_else(|| ctx.ro.lang.clone())
+		.unwrap_or_else(|| String::from("en"));
+
+	let mut book = Book::new(epub::Metadata {
+		title,
+		authors,
+		language,
+		identifier,
+		publisher: book.publisher,
+		description: book.description,
+		date: book.date,
+		modified: ctx.ro.now.unwrap_or_else(Timestamp::now).to_utc_string(),
+	});
+
+	for i in order {
+		let page = &site.pages[i];
+		buf.read_file(ctx, &page.src)?;
+
+		// Resources are packed by their paths relative to the chapter source
+		let vars = PageVars {
+			src: Some(page.src.clone()),
+			out: Some(page.src.clone()),
+			title: Some(page.title.clone()),
+			..PageVars::default()
+		};
+
+		let mut headings = Vec::new();
+		let mut heading = None::<Heading>;
+		let html = buf.render(ctx, vars, |event| {
+			match &event {
+				Event::Start(Tag::Heading {
+					level,
+					id: Some(id),
+					..
+				}) => {
+					heading = Some(Heading {
+						level: *level as u8,
+						id: id.to_string(),
+						title: String::new(),
 					});
 				}
-				match url.strip_suffix(".md") {
-					Some(without_ext) => Event::Start(Tag::Link {
-						link_type,
-						dest_url: format!("{without_ext}.html{rest}").into(),
-						title,
-						id,
-					}),
-					_ => Event::Start(Tag::Link {
+				Event::Text(s) | Event::Code(s) => {
+					if let Some(h) = &mut heading {
+						h.title.push_str(s);
+					}
+				}
+				Event::End(TagEnd::Heading(_)) => headings.extend(heading.take()),
+				_ => (),
+			}
+			rewrite_event(
+				&site,
+				root.as_deref(),
+				i,
+				&skipped,
+				skip_hidden,
+				ctx.ro,
+				event,
+			)
+		})?;
+
+		book.add_chapter(
+			&page.out,
+			&page.src,
+			page.title.clone(),
+			headings,
+			html.to_owned(),
+		);
+		info!("{}", page.src.display());
+	}
+
+	book.write(file)
+		.map_err(|e| anyhow!("error writing {}: {}", file.display(), e))?;
+	buf.validated()
+}
+
+/// Renders `files` into a single page, written to `out` or the standard output.
+fn convert_concat(files: &[PathBuf], out: Option<&Path>, ctx: Context) -> Result<()> {
+	let out = out.filter(|p| p.as_os_str() != "-");
+	let canonicalize = |p: &Path| {
+		BasePathBuf::new(p)
+			.and_then(|p| p.normalize())
+			.map_err(|e| anyhow!("failed to canonicalize the path {}: {}", p.display(), e))
+	};
+
+	// Relative URLs are rebased onto the directory of the output
+	let out_file = match out {
+		Some(out) => {
+			let dir = out.parent().filter(|p| !p.as_os_str().is_empty());
+			canonicalize(dir.unwrap_or(Path::new(".")))?
+				.into_path_buf()
+				.join(out.file_name().unwrap_or_default())
+		}
+		None => canonicalize(Path::new("."))?.into_path_buf().join("-"),
+	};
+
+	let mut buf = Buffer::new();
+	let mut srcs = Vec::with_capacity(files.len());
+	let mut sources = Vec::with_capacity(files.len());
+	let mut by_path = HashMap::with_capacity(files.len());
+	let mut skipped = HashSet::new();
+	for p in files {
+		buf.read_file(ctx, p)?;
+		let path = canonicalize(p)?.into_path_buf();
+		if unpublished(p, &front_matter(&buf.buf).0, ctx)? {
+			skipped.insert(path);
+			continue;
+		}
+		if by_path.insert(path, srcs.len()).is_some() {
+			bail!("{} is given more than once", p.display());
+		}
+		srcs.push(p.as_path());
+		sources.push(mem::take(&mut buf.buf));
+	}
+
+	// Every file gets an anchor to link to, and the ids of headings are made
+	// unique across the files
+	let mut seen = HashSet::new();
+	let mut anchors = Vec::with_capacity(srcs.len());
+	let mut ids = Vec::with_capacity(srcs.len());
+	let mut renamed = Vec::with_capacity(srcs.len());
+	for (p, source) in srcs.iter().zip(&sources) {
+		anchors.push(unique_id(&mut seen, &file_anchor(p)));
+		let mut map = HashMap::new();
+		let unique = heading_ids(source, ctx.ro)
+			.into_iter()
+			.map(|id| {
+				let new = unique_id(&mut seen, &id);
+				map.entry(id).or_insert_with(|| new.clone());
+				new
+			})
+			.collect::<Vec<_>>();
+		ids.push(unique);
+		renamed.push(map);
+	}
+
+	let names = site::Names::new(&srcs);
+	let mut body = String::new();
+	let mut html = String::new();
+	let mut md = None::<Metadata>;
+	let mut has_mermaid = false;
+	for (i, source) in sources.iter().enumerate() {
+		let src = srcs[i];
+		let rewrite = |url: &str| {
+			if let Some(id) = url.strip_prefix('#') {
+				return renamed[i].get(id).map(|id| format!("#{id}"));
+			}
+			let (path, rest) = split_url(url);
+			match resolve_link(None, src, path).and_then(|p| by_path.get(p.as_path())) {
+				Some(&to) => Some(match rest.split_once('#') {
+					Some((_, id)) if !id.is_empty() => {
+						format!("#{}", renamed[to].get(id).map_or(id, String::as_str))
+					}
+					_ => format!("#{}", anchors[to]),
+				}),
+				None => rebase_onto(src, &out_file, url),
+			}
+		};
+
+		// Wikilinks name files as in directories
+		let wikilink = |target: &str| {
+			let (name, section) = target.split_once('#').unwrap_or((target, ""));
+			let to = if name.trim().is_empty() {
+				i
+			} else {
+				match names.find(name) {
+					Ok(to) => to,
+					Err(e) => {
+						warn!(
+							"{}: failed to resolve the link [[{}]]: {}",
+							src.display(),
+							target,
+							e
+						);
+						return None;
+					}
+				}
+			};
+			Some(if section.is_empty() {
+				format!("#{}", anchors[to])
+			} else {
+				let id = site::slug(section);
+				format!("#{}", renamed[to].get(&id).unwrap_or(&id))
+			})
+		};
+
+		let mut headings = ids[i].iter();
+		html.clear();
+		let (mut page_md, mermaid) =
+			Doc::render_body(&mut html, source, ctx, Some(src), |event| match event {
+				Event::Start(Tag::Heading {
+					level,
+					id: Some(id),
+					classes,
+					attrs,
+				}) => Event::Start(Tag::Heading {
+					level,
+					id: Some(headings.next().map_or(id, |id| id.clone().into())),
+					classes,
+					attrs,
+				}),
+				Event::Start(Tag::Link {
+					link_type: link_type @ LinkType::WikiLink { .. },
+					dest_url,
+					title,
+					id,
+				}) => Event::Start(Tag::Link {
+					link_type,
+					dest_url: wikilink(&dest_url).map_or(dest_url, Into::into),
+					title,
+					id,
+				}),
+				Event::Start(Tag::Link {
+					link_type,
+					dest_url,
+					title,
+					id,
+				}) => {
+					check_unpublished_link(&skipped, None, src, &dest_url);
+					Event::Start(Tag::Link {
 						link_type,
-						dest_url,
+						dest_url: rewrite(&dest_url).map_or(dest_url, Into::into),
 						title,
 						id,
-					}),
+					})
 				}
-			}
-			_ => event,
-		})?;
+				Event::Start(Tag::Image {
+					link_type,
+					dest_url,
+					title,
+					id,
+				}) => Event::Start(Tag::Image {
+					link_type,
+					dest_url: rebase_onto(src, &out_file, &dest_url).map_or(dest_url, Into::into),
+					title,
+					id,
+				}),
+				_ => event,
+			});
+
+		for set in [&mut page_md.css, &mut page_md.script] {
+			*set = mem::take(set)
+				.into_iter()
+				.map(|mut r| {
+					if let Some(href) = rebase_onto(src, &out_file, &r.href) {
+						r.href = Owned(href);
+					}
+					r
+				})
+				.collect();
+		}
 
-		fs::write(&to, html).map_err(|e| anyhow!("error rendering to {}: {}", to.display(), e))?;
-		info!("{}", to.display());
+		match &mut md {
+			Some(md) => md.merge(page_md),
+			None => md = Some(page_md),
+		}
+		has_mermaid |= mermaid;
+
+		body.push_str("<section id=\"");
+		body.push_str(&anchors[i]);
+		body.push_str("\">\n");
+		body.push_str(html.trim_matches(WHITESPACE));
+		body.push_str("\n</section>\n");
 	}
 
-	Ok(())
+	let Some(md) = md else {
+		bail!("there are no pages to render");
+	};
+
+	let vars = PageVars {
+		src: out.map(Path::to_path_buf),
+		out: out.map(Path::to_path_buf),
+		..PageVars::default()
+	};
+	buf.rendered.clear();
+	Doc::with_body(&body, md, has_mermaid, ctx, vars).render_into(&mut buf.rendered)?;
+	let name = out.map_or_else(|| "<stdout>".into(), |p| p.display().to_string());
+	let html = buf.finish(ctx, &name)?;
+	match out {
+		Some(p) => fs::write(p, html)?,
+		None => print!("{html}"),
+	}
+	buf.validated()
+}
+
+/// Returns the ids the headings of `source` get, in order.
+fn heading_ids(source: &str, ro: &RenderOptions) -> Vec<String> {
+	let (md, body) = parse_front_matter::<Metadata>(source)
+		.unwrap_or_else(|| (Metadata::default(), source.trim_matches(WHITESPACE)));
+	let mut events = Parser::new_ext(body, markdown::options(ro, &md.markdown)).collect::<Vec<_>>();
+	if ro.heading_ids {
+		markdown::heading_ids(&mut events);
+	}
+	events
+		.into_iter()
+		.filter_map(|e| match e {
+			Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.into_string()),
+			_ => None,
+		})
+		.collect()
+}
+
+/// Returns the id of the section of a file in a combined page, such as
+/// `getting-started.md`.
+fn file_anchor(p: &Path) -> String {
+	let name = p.file_name().unwrap_or_default().to_string_lossy();
+	let mut id = String::with_capacity(name.len());
+	for c in name.chars() {
+		if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') {
+			id.extend(c.to_lowercase());
+		} else if c.is_whitespace() {
+			id.push('-');
+		}
+	}
+	if id.is_empty() {
+		id.push_str("file");
+	}
+	id
+}
+
+/// Returns `base`, or `base` with a number appended if it's already in `seen`,
+/// and adds it to `seen`.
+fn unique_id(seen: &mut HashSet<String>, base: &str) -> String {
+	let mut id = base.to_owned();
+	let mut n = 0;
+	while seen.contains(&id) {
+		n += 1;
+		id = format!("{base}-{n}");
+	}
+	seen.insert(id.clone());
+	id
+}
+
+/// Rewrites a relative URL in `src` so that it works from `out`, returning
+/// `None` if it's unchanged.
+///
+/// `out` must be normalized. URLs pointing to files that don't exist are left
+/// alone.
+fn rebase_onto(src: &Path, out: &Path, url: &str) -> Option<String> {
+	if url.is_empty() || url.starts_with(['/', '#', '?']) || link::has_scheme(url) {
+		return None;
+	}
+
+	let (path, rest) = split_url(url);
+	let target = resolve_link(None, src, path)?;
+	if target.parent().ok()?.map(BasePath::as_path) == out.parent() {
+		return None;
+	}
+	Some(format!(
+		"{}{rest}",
+		site::relative_url(out, target.as_path())
+	))
 }
 
 fn get_args() -> impl IntoIterator<Item = OsString> {
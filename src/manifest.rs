@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	collections::BTreeSet,
+	fs,
+	io::ErrorKind,
+	path::{
+		Component,
+		Path,
+		PathBuf,
+	},
+};
+
+use anyhow::{
+	anyhow,
+	Result,
+};
+use log::{
+	info,
+	warn,
+};
+
+/// The file in the output directory listing the files written by the last
+/// build.
+const FILE_NAME: &str = ".mars-manifest";
+
+/// Keeps track of the files written into an output directory.
+#[derive(Default)]
+pub struct Manifest {
+	/// Paths relative to the output directory.
+	files: BTreeSet<PathBuf>,
+}
+
+impl Manifest {
+	/// Records a file written into `out_dir`.
+	pub fn add(&mut self, out_dir: &Path, file: &Path) {
+		if let Ok(rel) = file.strip_prefix(out_dir) {
+			self.files.insert(rel.to_path_buf());
+		}
+	}
+
+	/// Removes the files written by the previous build that weren't written by
+	/// this one, then saves the manifest.
+	///
+	/// Only files listed in the previous manifest are removed, along with the
+	/// directories left empty.
+	pub fn clean(&self, out_dir: &Path) -> Result<()> {
+		let path = out_dir.join(FILE_NAME);
+
+		let old = match fs::read_to_string(&path) {
+			Ok(s) => s,
+			Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+			Err(e) => return Err(anyhow!("failed to read {}: {}", path.display(), e)),
+		};
+
+		for line in old.lines().filter(|s| !s.is_empty()) {
+			let rel = Path::new(line);
+			// Don't trust an edited manifest with paths outside the directory
+			if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+				warn!("{}: ignoring the entry {}", path.display(), line);
+				continue;
+			}
+			if self.files.contains(rel) || line == FILE_NAME {
+				continue;
+			}
+
+			let file = out_dir.join(rel);
+			match fs::remove_file(&file) {
+				Ok(_) => info!("removed {}", file.display()),
+				Err(e) if e.kind() == ErrorKind::NotFound => (),
+				Err(e) => warn!("failed to remove {}: {}", file.display(), e),
+			}
+
+			// Remove the directories left empty, stopping at the output directory
+			for dir in rel.ancestors().skip(1) {
+				if dir.as_os_str().is_empty() || fs::remove_dir(out_dir.join(dir)).is_err() {
+					break;
+				}
+				info!("removed {}", out_dir.join(dir).display());
+			}
+		}
+
+		let mut new = String::with_capacity(self.files.len() * 32);
+		for p in &self.files {
+			// Use `/` on every platform so the manifest is portable
+			let components = p
+				.components()
+				.map(|c| c.as_os_str().to_string_lossy())
+				.collect::<Vec<_>>();
+			new.push_str(&components.join("/"));
+			new.push('\n');
+		}
+
+		fs::write(&path, new).map_err(|e| anyhow!("failed to write {}: {}", path.display(), e))
+	}
+}
@@ -0,0 +1,233 @@
+// SPDX-License-Identifier: MIT
+
+use std::collections::{
+	BTreeMap,
+	HashSet,
+};
+
+use clap::ValueEnum;
+use pulldown_cmark::{
+	Event,
+	Options,
+	Tag,
+	TagEnd,
+};
+use serde::Deserialize;
+
+use crate::{
+	site::slug,
+	RenderOptions,
+};
+
+/// An optional Markdown syntax extension.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Extension {
+	/// GitHub style tables
+	Tables,
+	/// Footnotes, as in `text[^1]`
+	Footnotes,
+	/// `~~strikethrough~~`
+	Strikethrough,
+	/// Task list items, as in `- [x] done`
+	Tasklists,
+	/// Curly quotes, and dashes and ellipses from `--`, `---` and `...`
+	SmartPunctuation,
+	/// Custom ids and classes on headings, as in `# Title {#id .class}`
+	HeadingAttributes,
+	/// YAML and TOML metadata blocks inside the document, which are not
+	/// rendered
+	MetadataBlocks,
+	/// `$inline$` and `$$display$$` math
+	Math,
+	/// GitHub style blockquote alerts, as in `> [!NOTE]`
+	Gfm,
+	/// Definition lists
+	DefinitionList,
+	/// `^superscript^`
+	Superscript,
+	/// `~subscript~`
+	Subscript,
+	/// `[[Page Name]]` and `[[Page Name|label]]` links, which are resolved to
+	/// pages while converting a directory
+	Wikilinks,
+}
+
+impl Extension {
+	fn options(self) -> Options {
+		match self {
+			Self::Tables => Options::ENABLE_TABLES,
+			Self::Footnotes => Options::ENABLE_FOOTNOTES,
+			Self::Strikethrough => Options::ENABLE_STRIKETHROUGH,
+			Self::Tasklists => Options::ENABLE_TASKLISTS,
+			Self::SmartPunctuation => Options::ENABLE_SMART_PUNCTUATION,
+			Self::HeadingAttributes => Options::ENABLE_HEADING_ATTRIBUTES,
+			Self::MetadataBlocks => {
+				Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+					| Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS
+			}
+			Self::Math => Options::ENABLE_MATH,
+			Self::Gfm => Options::ENABLE_GFM,
+			Self::DefinitionList => Options::ENABLE_DEFINITION_LIST,
+			Self::Superscript => Options::ENABLE_SUPERSCRIPT,
+			Self::Subscript => Options::ENABLE_SUBSCRIPT,
+			Self::Wikilinks => Options::ENABLE_WIKILINKS,
+		}
+	}
+
+	fn set(self, opts: &mut Options, on: bool) {
+		match (self, on) {
+			(_, true) => opts.insert(self.options()),
+			// Old style footnotes imply footnotes
+			(Self::Footnotes, false) => opts.remove(Options::ENABLE_OLD_FOOTNOTES),
+			(_, false) => opts.remove(self.options()),
+		}
+	}
+}
+
+/// Extensions that are not enabled by default, since they change how existing
+/// documents render: `~x~` would no longer be strikethrough, for example.
+const OPT_IN: Options = Options::ENABLE_SUPERSCRIPT
+	.union(Options::ENABLE_SUBSCRIPT)
+	.union(Options::ENABLE_WIKILINKS);
+
+/// Returns the parser options for a page.
+///
+/// Every extension but superscript, subscript and wikilinks is enabled by
+/// default, or none with `--commonmark`; wikilinks are enabled too where they
+/// are resolved to pages. Then `--enable` and `--disable` apply, followed by
+/// the `markdown` map in the front matter.
+pub fn options(ro: &RenderOptions, overrides: &BTreeMap<Extension, bool>) -> Options {
+	let mut opts = if ro.commonmark {
+		Options::empty()
+	} else if ro.wikilinks {
+		Options::all().difference(OPT_IN) | Options::ENABLE_WIKILINKS
+	} else {
+		Options::all().difference(OPT_IN)
+	};
+
+	for &ext in &ro.enable {
+		ext.set(&mut opts, true);
+	}
+	for &ext in &ro.disable {
+		ext.set(&mut opts, false);
+	}
+	for (&ext, &on) in overrides {
+		ext.set(&mut opts, on);
+	}
+
+	opts
+}
+
+/// Gives the headings without an id one made from their text, the way GitHub
+/// does; the second `Usage` heading gets `usage-1` and so on.
+pub fn heading_ids(events: &mut [Event]) {
+	let mut seen = events
+		.iter()
+		.filter_map(|e| match e {
+			Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.to_string()),
+			_ => None,
+		})
+		.collect::<HashSet<_>>();
+
+	for i in 0..events.len() {
+		if !matches!(events[i], Event::Start(Tag::Heading { id: None, .. })) {
+			continue;
+		}
+
+		let mut text = String::new();
+		for e in &events[i + 1..] {
+			match e {
+				Event::End(TagEnd::Heading(_)) => break,
+				Event::Text(s) | Event::Code(s) => text.push_str(s),
+				_ => (),
+			}
+		}
+
+		let base = slug(&text);
+		if base.is_empty() {
+			continue;
+		}
+		let mut id = base.clone();
+		let mut n = 0;
+		while seen.contains(&id) {
+			n += 1;
+			id = format!("{base}-{n}");
+		}
+		seen.insert(id.clone());
+
+		if let Event::Start(Tag::Heading { id: slot, .. }) = &mut events[i] {
+			*slot = Some(id.into());
+		}
+	}
+}
+
+/// Replaces GitHub emoji shortcodes such as `:rocket:` with the emoji,
+/// wrapping them in `<span class="emoji">` if `span` is set.
+///
+/// Code spans and code blocks are left alone.
+pub fn emoji(events: &mut Vec<Event>, span: bool) {
+	let mut out = Vec::with_capacity(events.len());
+	let mut in_code = false;
+	let mut text = String::new();
+
+	for e in std::mem::take(events) {
+		match e {
+			Event::Text(s) if !in_code => {
+				text.push_str(&s);
+				continue;
+			}
+			Event::Start(Tag::CodeBlock(_)) => in_code = true,
+			Event::End(TagEnd::CodeBlock) => in_code = false,
+			_ => (),
+		}
+		replace_shortcodes(&mut out, &std::mem::take(&mut text), span);
+		out.push(e);
+	}
+	replace_shortcodes(&mut out, &text, span);
+
+	*events = out;
+}
+
+fn replace_shortcodes(out: &mut Vec<Event>, text: &str, span: bool) {
+	if text.is_empty() {
+		return;
+	}
+
+	let mut buf = String::with_capacity(text.len());
+	let mut rest = text;
+	while let Some(i) = rest.find(':') {
+		let after = &rest[i + 1..];
+		let end = after
+			.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-')))
+			.unwrap_or(after.len());
+
+		let emoji = after[end..]
+			.starts_with(':')
+			.then(|| emojis::get_by_shortcode(&after[..end]))
+			.flatten();
+		let Some(emoji) = emoji.filter(|_| end > 0) else {
+			buf.push_str(&rest[..=i]);
+			rest = after;
+			continue;
+		};
+
+		buf.push_str(&rest[..i]);
+		if span {
+			if !buf.is_empty() {
+				out.push(Event::Text(std::mem::take(&mut buf).into()));
+			}
+			out.push(Event::Html(
+				format!("<span class=\"emoji\">{}</span>", emoji.as_str()).into(),
+			));
+		} else {
+			buf.push_str(emoji.as_str());
+		}
+		rest = &after[end + 1..];
+	}
+
+	buf.push_str(rest);
+	if !buf.is_empty() {
+		out.push(Event::Text(buf.into()));
+	}
+}
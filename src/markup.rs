@@ -0,0 +1,21 @@
+// SPDX-License-Identifier: MIT
+
+/// Returns the byte offset of the first ASCII case insensitive match of
+/// `needle` in `haystack`.
+pub fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+	haystack
+		.as_bytes()
+		.windows(needle.len())
+		.position(|w| w.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Replaces the character references written by the Markdown renderer in
+/// attribute values.
+pub fn unescape(s: &str) -> String {
+	s.replace("&quot;", "\"")
+		.replace("&#39;", "'")
+		.replace("&#x27;", "'")
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&amp;", "&")
+}
@@ -0,0 +1,467 @@
+// SPDX-License-Identifier: MIT
+
+use std::borrow::Cow;
+
+use crate::markup::find_ignore_case;
+
+/// Elements around which whitespace is not rendered.
+const BLOCKS: &[&str] = &[
+	"address",
+	"article",
+	"aside",
+	"base",
+	"blockquote",
+	"body",
+	"caption",
+	"col",
+	"colgroup",
+	"dd",
+	"details",
+	"dialog",
+	"div",
+	"dl",
+	"dt",
+	"fieldset",
+	"figcaption",
+	"figure",
+	"footer",
+	"form",
+	"h1",
+	"h2",
+	"h3",
+	"h4",
+	"h5",
+	"h6",
+	"head",
+	"header",
+	"hgroup",
+	"hr",
+	"html",
+	"li",
+	"link",
+	"main",
+	"meta",
+	"nav",
+	"noscript",
+	"ol",
+	"option",
+	"p",
+	"pre",
+	"script",
+	"section",
+	"style",
+	"summary",
+	"table",
+	"tbody",
+	"td",
+	"tfoot",
+	"th",
+	"thead",
+	"title",
+	"tr",
+	"ul",
+];
+
+/// Elements whose contents are not parsed as HTML.
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title"];
+
+/// Script types minified as JavaScript; JSON is a subset of it.
+const SCRIPT_TYPES: &[&str] = &[
+	"",
+	"application/javascript",
+	"application/json",
+	"application/ld+json",
+	"importmap",
+	"module",
+	"text/javascript",
+];
+
+/// Minifies an HTML document into `buf`.
+///
+/// Comments are removed, whitespace outside `<pre>` and `<textarea>` is
+/// collapsed, attribute values are unquoted where possible, and the contents
+/// of `<style>` and `<script>` elements are minified with [css] and [js].
+pub fn html(src: &str, buf: &mut String) {
+	buf.reserve(src.len());
+	// How many `<pre>` elements we're in
+	let mut pre = 0_usize;
+	// Whether the whitespace at the start of the next text is dropped
+	let mut trim = true;
+	// Where the contents of the last raw text element end, so that their
+	// whitespace is kept
+	let mut raw_end = 0;
+	let mut rest = src;
+
+	while !rest.is_empty() {
+		let Some(i) = rest.find('<') else {
+			text(buf, rest, pre > 0, &mut trim);
+			break;
+		};
+		text(buf, &rest[..i], pre > 0, &mut trim);
+		rest = &rest[i..];
+
+		if let Some(s) = rest.strip_prefix("<!--") {
+			rest = s.find("-->").map_or("", |i| &s[i + 3..]);
+			continue;
+		}
+		if rest.starts_with("<!") || rest.starts_with("<?") {
+			let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+			buf.push_str(&rest[..end]);
+			rest = &rest[end..];
+			trim = true;
+			continue;
+		}
+
+		let closing = rest.starts_with("</");
+		let s = &rest[if closing { 2 } else { 1 }..];
+		let len = s
+			.find(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != ':')
+			.unwrap_or(s.len());
+		if len == 0 || !s.as_bytes()[0].is_ascii_alphabetic() {
+			// Not a tag
+			text(buf, "<", pre > 0, &mut trim);
+			rest = &rest[1..];
+			continue;
+		}
+
+		let name = s[..len].to_ascii_lowercase();
+		let block = BLOCKS.contains(&name.as_str());
+		if block && pre == 0 && buf.len() > raw_end && buf.ends_with(' ') {
+			buf.pop();
+		}
+
+		let (end, attrs) = tag(buf, rest, closing, len);
+		rest = &rest[end..];
+
+		if name == "pre" {
+			if closing {
+				pre = pre.saturating_sub(1);
+			} else {
+				pre += 1;
+			}
+		}
+		if block {
+			trim = true;
+		}
+
+		if closing || !RAW_TEXT.contains(&name.as_str()) {
+			continue;
+		}
+
+		// Raw text, up to the closing tag
+		let len = find_ignore_case(rest, &format!("</{name}")).unwrap_or(rest.len());
+		let content = &rest[..len];
+		rest = &rest[len..];
+		match name.as_str() {
+			"style" | "script" => buf.push_str(&raw_text(&name, attrs, content)),
+			"title" => {
+				let mut trim = true;
+				text(buf, content, false, &mut trim);
+				if buf.ends_with(' ') {
+					buf.pop();
+				}
+			}
+			_ => buf.push_str(content),
+		}
+		raw_end = buf.len();
+	}
+
+	if buf.ends_with(' ') {
+		buf.pop();
+	}
+}
+
+/// Writes text with its whitespace collapsed, unless `keep` is set.
+fn text(buf: &mut String, s: &str, keep: bool, trim: &mut bool) {
+	if keep {
+		buf.push_str(s);
+		*trim = false;
+		return;
+	}
+
+	for c in s.chars() {
+		if c.is_ascii_whitespace() {
+			if !*trim && !buf.ends_with(' ') {
+				buf.push(' ');
+			}
+		} else {
+			buf.push(c);
+			*trim = false;
+		}
+	}
+}
+
+/// Writes the tag at the start of `s`, returning its length and the value of
+/// its `type` attribute.
+fn tag<'a>(buf: &mut String, s: &'a str, closing: bool, name_len: usize) -> (usize, &'a str) {
+	let start = if closing { 2 } else { 1 };
+	buf.push_str(&s[..start + name_len]);
+	let mut rest = &s[start + name_len..];
+	let mut ty = "";
+
+	loop {
+		rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+		if rest.is_empty() {
+			break;
+		}
+		if let Some(r) = rest.strip_prefix('>') {
+			buf.push('>');
+			rest = r;
+			break;
+		}
+		if let Some(r) = rest.strip_prefix("/>") {
+			buf.push_str("/>");
+			rest = r;
+			break;
+		}
+
+		let n = rest
+			.find(|c: char| c.is_ascii_whitespace() || matches!(c, '=' | '>'))
+			.unwrap_or(rest.len())
+			// A stray `/`
+			.max(1);
+		let name = &rest[..n];
+		buf.push(' ');
+		buf.push_str(name);
+		rest = rest[n..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+
+		let Some(v) = rest.strip_prefix('=') else {
+			continue;
+		};
+		let v = v.trim_start_matches(|c: char| c.is_ascii_whitespace());
+		let (value, len) = match v.chars().next() {
+			Some(q @ ('"' | '\'')) => {
+				let close = v[1..].find(q).map_or(v.len(), |i| i + 1);
+				(&v[1..close], usize::min(close + 1, v.len()))
+			}
+			_ => {
+				let n = v
+					.find(|c: char| c.is_ascii_whitespace() || c == '>')
+					.unwrap_or(v.len());
+				(&v[..n], n)
+			}
+		};
+		rest = &v[len..];
+
+		if name.eq_ignore_ascii_case("type") {
+			ty = value;
+		}
+
+		buf.push('=');
+		let unquoted = !value.is_empty()
+			&& !value.contains(|c: char| {
+				c.is_ascii_whitespace() || matches!(c, '"' | '\'' | '=' | '<' | '>' | '`')
+			});
+		if unquoted {
+			buf.push_str(value);
+			if rest.trim_start().starts_with('/') {
+				// `a=b/>` would make `/` a part of the value
+				buf.push(' ');
+			}
+		} else if value.contains('"') {
+			buf.push('\'');
+			buf.push_str(value);
+			buf.push('\'');
+		} else {
+			buf.push('"');
+			buf.push_str(value);
+			buf.push('"');
+		}
+	}
+
+	(s.len() - rest.len(), ty)
+}
+
+/// Minifies the contents of a `<style>` or `<script>` element the way [html]
+/// does.
+pub fn raw_text<'a>(tag: &str, ty: &str, content: &'a str) -> Cow<'a, str> {
+	match tag {
+		"style" => Cow::Owned(css(content)),
+		"script" if is_script(ty) => Cow::Owned(js(content)),
+		_ => Cow::Borrowed(content),
+	}
+}
+
+fn is_script(ty: &str) -> bool {
+	let ty = ty.split(';').next().unwrap_or_default().trim();
+	SCRIPT_TYPES.iter().any(|t| t.eq_ignore_ascii_case(ty))
+}
+
+/// Skips a quoted string starting at `i`, returning the index after it.
+fn skip_string(b: &[u8], mut i: usize) -> usize {
+	let q = b[i];
+	i += 1;
+	while i < b.len() && b[i] != q {
+		if b[i] == b'\\' {
+			i += 1;
+		}
+		i += 1;
+	}
+	usize::min(i + 1, b.len())
+}
+
+/// Minifies CSS: comments are removed, and whitespace is collapsed and
+/// dropped around punctuation.
+fn css(src: &str) -> String {
+	let b = src.as_bytes();
+	let mut out = Vec::with_capacity(b.len());
+	let mut i = 0;
+	let mut space = false;
+
+	while i < b.len() {
+		match b[i] {
+			b'"' | b'\'' => {
+				let end = skip_string(b, i);
+				let after_punct = out.last().is_some_and(|p| b"{};,>:".contains(p));
+				if space && !after_punct && !out.is_empty() {
+					out.push(b' ');
+				}
+				space = false;
+				out.extend_from_slice(&b[i..end]);
+				i = end;
+			}
+			b'/' if b.get(i + 1) == Some(&b'*') => {
+				i = src[i + 2..].find("*/").map_or(b.len(), |n| i + n + 4);
+				space = true;
+			}
+			c if c.is_ascii_whitespace() => {
+				space = true;
+				i += 1;
+			}
+			c => {
+				let punct = b"{};,>".contains(&c);
+				let after_punct = out.last().is_some_and(|p| b"{};,>:".contains(p));
+				if space && !punct && !after_punct && !out.is_empty() {
+					out.push(b' ');
+				}
+				space = false;
+				if c == b'}' && out.last() == Some(&b';') {
+					out.pop();
+				}
+				out.push(c);
+				i += 1;
+			}
+		}
+	}
+
+	String::from_utf8(out).unwrap_or_else(|_| src.to_owned())
+}
+
+/// Minifies JavaScript or JSON: comments are removed and whitespace is
+/// collapsed, keeping line breaks where automatic semicolon insertion may
+/// depend on them.
+fn js(src: &str) -> String {
+	let b = src.as_bytes();
+	let mut out = Vec::<u8>::with_capacity(b.len());
+	let mut i = 0;
+	// The whitespace seen since the last token: 0, b' ' or b'\n'
+	let mut space = 0_u8;
+
+	let is_punct = |c: u8| b"{}()[];,:=<>?!&|*%^~".contains(&c);
+
+	while i < b.len() {
+		let c = b[i];
+		if c.is_ascii_whitespace() {
+			if c == b'\n' || c == b'\r' {
+				space = b'\n';
+			} else if space == 0 {
+				space = b' ';
+			}
+			i += 1;
+			continue;
+		}
+
+		if c == b'/' && b.get(i + 1) == Some(&b'/') {
+			i = src[i..].find('\n').map_or(b.len(), |n| i + n);
+			continue;
+		}
+		if c == b'/' && b.get(i + 1) == Some(&b'*') {
+			match src[i + 2..].find("*/") {
+				Some(n) => {
+					if src[i..i + n + 2].contains('\n') {
+						space = b'\n';
+					} else if space == 0 {
+						space = b' ';
+					}
+					i += n + 4;
+				}
+				None => i = b.len(),
+			}
+			continue;
+		}
+
+		if space != 0 {
+			if let Some(&p) = out.last() {
+				if space == b'\n' && !b"{([;,:=".contains(&p) && !b"})]".contains(&c) {
+					out.push(b'\n');
+				} else if !is_punct(p) && !is_punct(c) {
+					out.push(b' ');
+				}
+			}
+			space = 0;
+		}
+
+		let end = match c {
+			b'"' | b'\'' => skip_string(b, i),
+			b'`' => skip_template(b, i),
+			// A regular expression, if it can't be a division
+			b'/' if out
+				.last()
+				.is_none_or(|p| b"(,=:[!&|?{};+-*%<>~^".contains(p)) =>
+			{
+				skip_regex(b, i)
+			}
+			_ => i + 1,
+		};
+		out.extend_from_slice(&b[i..end]);
+		i = end;
+	}
+
+	String::from_utf8(out).unwrap_or_else(|_| src.to_owned())
+}
+
+/// Skips a template literal starting at `i`, with its substitutions.
+fn skip_template(b: &[u8], mut i: usize) -> usize {
+	i += 1;
+	let mut depth = 0_usize;
+	while i < b.len() {
+		match b[i] {
+			b'\\' => i += 1,
+			b'`' if depth == 0 => return i + 1,
+			b'$' if b.get(i + 1) == Some(&b'{') => {
+				depth += 1;
+				i += 1;
+			}
+			b'}' if depth > 0 => depth -= 1,
+			b'"' | b'\'' if depth > 0 => {
+				i = skip_string(b, i);
+				continue;
+			}
+			b'`' => {
+				i = skip_template(b, i);
+				continue;
+			}
+			_ => (),
+		}
+		i += 1;
+	}
+	b.len()
+}
+
+/// Skips a regular expression literal starting at `i`.
+fn skip_regex(b: &[u8], mut i: usize) -> usize {
+	i += 1;
+	let mut class = false;
+	while i < b.len() {
+		match b[i] {
+			b'\\' => i += 1,
+			b'[' => class = true,
+			b']' => class = false,
+			b'/' if !class => return i + 1,
+			b'\n' => return i,
+			_ => (),
+		}
+		i += 1;
+	}
+	b.len()
+}
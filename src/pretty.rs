@@ -3,6 +3,7 @@
 #![allow(clippy::type_complexity)]
 
 use tidier::{
+	CustomTags,
 	FormatOptions,
 	LineEnding,
 };
@@ -26,6 +27,8 @@ pub enum FormatArg {
 	NewlineAfterBr(bool),
 	MergeDivs(bool),
 	MergeSpans(bool),
+	CustomTags(CustomTags),
+	AsciiSymbols(bool),
 }
 
 fn error(arg: &str, val: &str, msg: &str) -> String {
@@ -34,8 +37,6 @@ fn error(arg: &str, val: &str, msg: &str) -> String {
 
 impl FormatArg {
 	pub fn parse(s: &str) -> Result<Self, String> {
-		use FormatArg::*;
-
 		let (orig_arg, orig_val) = s.split_once([':', '=']).unwrap_or((s, ""));
 
 		let orig_arg = orig_arg.trim();
@@ -44,14 +45,15 @@ impl FormatArg {
 		let val = orig_val.to_lowercase();
 
 		const BOOLS: &[(&str, fn(bool) -> FormatArg)] = &[
-			("indent-attributes", IndentAttributes),
-			("indent-cdata", IndentCdata),
-			("remove-comments", RemoveComments),
-			("join-classes", JoinClasses),
-			("join-styles", JoinStyles),
-			("newline-after-br", NewlineAfterBr),
-			("merge-divs", MergeDivs),
-			("merge-spans", MergeSpans),
+			("indent-attributes", FormatArg::IndentAttributes),
+			("indent-cdata", FormatArg::IndentCdata),
+			("remove-comments", FormatArg::RemoveComments),
+			("join-classes", FormatArg::JoinClasses),
+			("join-styles", FormatArg::JoinStyles),
+			("newline-after-br", FormatArg::NewlineAfterBr),
+			("merge-divs", FormatArg::MergeDivs),
+			("merge-spans", FormatArg::MergeSpans),
+			("ascii-symbols", FormatArg::AsciiSymbols),
 		];
 
 		match arg.as_str() {
@@ -83,6 +85,18 @@ impl FormatArg {
 					"value must be one of 'lf', 'crlf' and 'cr'",
 				)),
 			},
+			"custom-tags" => match val.as_str() {
+				"no" => Ok(Self::CustomTags(CustomTags::No)),
+				"blocklevel" | "block" => Ok(Self::CustomTags(CustomTags::Blocklevel)),
+				"empty" => Ok(Self::CustomTags(CustomTags::Empty)),
+				"inline" => Ok(Self::CustomTags(CustomTags::Inline)),
+				"pre" => Ok(Self::CustomTags(CustomTags::Pre)),
+				_ => Err(error(
+					&arg,
+					orig_val,
+					"value must be one of 'no', 'blocklevel', 'empty', 'inline' and 'pre'",
+				)),
+			},
 			_ => {
 				for &(name, f) in BOOLS {
 					if arg == name {
@@ -124,6 +138,8 @@ impl FormatArg {
 			NewlineAfterBr(x) => o.br_newline = x,
 			MergeDivs(x) => o.merge_divs = x,
 			MergeSpans(x) => o.merge_spans = x,
+			CustomTags(x) => o.custom_tags = x,
+			AsciiSymbols(x) => o.ascii_symbols = x,
 		}
 	}
 }
@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MIT
+
+/// Print rules that don't depend on the page.
+const RULES: &str = "\
+nav.sidebar,
+nav.breadcrumbs,
+nav.page-nav {
+	display: none;
+}
+h1,
+body > section {
+	break-before: page;
+}
+body > :first-child,
+body > section:first-child > :first-child,
+body > section > h1:first-child {
+	break-before: auto;
+}
+h1,
+h2,
+h3,
+h4,
+h5,
+h6 {
+	break-after: avoid;
+}
+pre,
+blockquote,
+table,
+figure,
+img,
+svg {
+	break-inside: avoid;
+}
+p {
+	orphans: 3;
+	widows: 3;
+}
+pre {
+	white-space: pre-wrap;
+}
+thead {
+	display: table-header-group;
+}
+img,
+svg {
+	max-width: 100%;
+}
+a[href^=\"http://\"]::after,
+a[href^=\"https://\"]::after {
+	content: \" (\" attr(href) \")\";
+	font-size: 90%;
+	word-break: break-all;
+}
+a:has(img)::after {
+	content: none;
+}";
+
+/// Returns the print stylesheet, with `header` and `footer` repeated on every
+/// printed page.
+pub fn stylesheet(header: Option<&str>, footer: Option<&str>) -> String {
+	let mut css = String::from("@page {\n\tmargin: 2cm 1.8cm;\n");
+	for (area, text) in [("top-center", header), ("bottom-center", footer)] {
+		if let Some(text) = text.filter(|s| !s.trim().is_empty()) {
+			css.push_str(&format!(
+				"\t@{area} {{\n\t\tcontent: {};\n\t}}\n",
+				string(text)
+			));
+		}
+	}
+	css.push_str("\t@bottom-right {\n\t\tcontent: counter(page) \" / \" counter(pages);\n\t}\n}\n");
+	css.push_str(RULES);
+	css
+}
+
+/// Quotes `s` as a CSS string, escaping `<` so that it can't end the
+/// `<style>` element.
+fn string(s: &str) -> String {
+	let mut buf = String::with_capacity(s.len() + 2);
+	buf.push('"');
+	for c in s.trim().chars() {
+		match c {
+			'"' | '\\' => {
+				buf.push('\\');
+				buf.push(c);
+			}
+			'\n' => buf.push_str("\\a "),
+			'<' => buf.push_str("\\3c "),
+			'&' => buf.push_str("\\26 "),
+			c if c.is_control() => (),
+			c => buf.push(c),
+		}
+	}
+	buf.push('"');
+	buf
+}
@@ -0,0 +1,264 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	collections::{
+		HashMap,
+		HashSet,
+	},
+	path::Path,
+	sync::OnceLock,
+};
+
+use ammonia::Builder;
+use log::warn;
+use pulldown_cmark::{
+	Event,
+	Tag,
+};
+
+/// The elements kept by `--sanitize`.
+///
+/// Besides the usual formatting elements, this includes the ones written by
+/// mars itself, such as `figure` and the task list checkboxes.
+const TAGS: &[&str] = &[
+	"a",
+	"abbr",
+	"b",
+	"bdi",
+	"bdo",
+	"blockquote",
+	"br",
+	"caption",
+	"cite",
+	"code",
+	"col",
+	"colgroup",
+	"data",
+	"dd",
+	"del",
+	"details",
+	"dfn",
+	"div",
+	"dl",
+	"dt",
+	"em",
+	"figcaption",
+	"figure",
+	"h1",
+	"h2",
+	"h3",
+	"h4",
+	"h5",
+	"h6",
+	"hr",
+	"i",
+	"img",
+	"input",
+	"ins",
+	"kbd",
+	"li",
+	"mark",
+	"ol",
+	"p",
+	"pre",
+	"q",
+	"rp",
+	"rt",
+	"ruby",
+	"s",
+	"samp",
+	"small",
+	"span",
+	"strong",
+	"sub",
+	"summary",
+	"sup",
+	"table",
+	"tbody",
+	"td",
+	"tfoot",
+	"th",
+	"thead",
+	"time",
+	"tr",
+	"u",
+	"ul",
+	"var",
+	"wbr",
+];
+
+/// The attributes allowed on every element.
+const GENERIC_ATTRIBUTES: &[&str] = &["class", "id", "lang", "title"];
+
+/// The attributes allowed on specific elements.
+const TAG_ATTRIBUTES: &[(&str, &[&str])] = &[
+	// No `target`: a page opened in a new tab could navigate the opener
+	("a", &["href", "hreflang", "rel"]),
+	("bdo", &["dir"]),
+	("blockquote", &["cite"]),
+	("col", &["span"]),
+	("colgroup", &["span"]),
+	("data", &["value"]),
+	("del", &["cite", "datetime"]),
+	("details", &["open"]),
+	("img", &["alt", "height", "loading", "src", "width"]),
+	("input", &["checked", "disabled", "type"]),
+	("ins", &["cite", "datetime"]),
+	("li", &["value"]),
+	("ol", &["reversed", "start", "type"]),
+	("q", &["cite"]),
+	// Only `text-align` is kept from the styles, for table column alignment
+	("td", &["colspan", "rowspan", "style"]),
+	("th", &["colspan", "rowspan", "scope", "style"]),
+	("time", &["datetime"]),
+];
+
+/// The URL schemes allowed in links and images. Relative URLs are always
+/// allowed.
+const URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Attributes that hold URLs.
+const URL_ATTRIBUTES: &[&str] = &["href", "src", "cite"];
+
+fn builder() -> &'static Builder<'static> {
+	static BUILDER: OnceLock<Builder<'static>> = OnceLock::new();
+	BUILDER.get_or_init(|| {
+		let mut b = Builder::empty();
+		b.tags(TAGS.iter().copied().collect())
+			.clean_content_tags(HashSet::from(["script", "style"]))
+			.generic_attributes(GENERIC_ATTRIBUTES.iter().copied().collect())
+			.tag_attributes(
+				TAG_ATTRIBUTES
+					.iter()
+					.map(|&(tag, attrs)| (tag, attrs.iter().copied().collect()))
+					.collect::<HashMap<_, _>>(),
+			)
+			.url_schemes(URL_SCHEMES.iter().copied().collect())
+			.filter_style_properties(HashSet::from(["text-align"]))
+			.link_rel(None)
+			.strip_comments(true);
+		b
+	})
+}
+
+/// Removes the elements, attributes and URLs that are not allowed from the
+/// HTML of a page body.
+pub fn clean(html: &str) -> String {
+	builder().clean(html).to_string()
+}
+
+fn allowed_scheme(url: &str) -> bool {
+	let url = url.trim();
+	match url.find([':', '/', '?', '#']) {
+		Some(i) if url.as_bytes()[i] == b':' => URL_SCHEMES
+			.iter()
+			.any(|s| s.eq_ignore_ascii_case(&url[..i])),
+		_ => true,
+	}
+}
+
+fn allowed_attribute(tag: &str, attr: &str) -> bool {
+	GENERIC_ATTRIBUTES.contains(&attr)
+		|| TAG_ATTRIBUTES
+			.iter()
+			.any(|&(t, attrs)| t == tag && attrs.contains(&attr))
+}
+
+/// Finds what [clean] will remove from the raw HTML and the links of a page,
+/// and reports it.
+pub fn report(events: &[Event], src: Option<&Path>) {
+	let mut removed = Vec::<String>::new();
+	let mut add = |s: String| {
+		if !removed.contains(&s) {
+			removed.push(s);
+		}
+	};
+
+	for e in events {
+		match e {
+			Event::Html(html) | Event::InlineHtml(html) => scan(html, &mut add),
+			Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. })
+				if !allowed_scheme(dest_url) =>
+			{
+				add(format!("the URL {dest_url}"));
+			}
+			_ => (),
+		}
+	}
+
+	if !removed.is_empty() {
+		warn!(
+			"{}: removed {}",
+			src.map_or_else(|| "<stdin>".into(), |p| p.display().to_string()),
+			removed.join(", ")
+		);
+	}
+}
+
+/// Finds the tags, attributes and URLs that are not allowed in a fragment of
+/// raw HTML.
+fn scan(html: &str, add: &mut impl FnMut(String)) {
+	let mut rest = html;
+	while let Some(i) = rest.find('<') {
+		rest = &rest[i + 1..];
+		let closing = rest.starts_with('/');
+		let s = rest.trim_start_matches('/');
+
+		let end = s
+			.find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+			.unwrap_or(s.len());
+		if end == 0 || !s.as_bytes()[0].is_ascii_alphabetic() {
+			continue;
+		}
+		let tag = s[..end].to_ascii_lowercase();
+		if !TAGS.contains(&tag.as_str()) {
+			if !closing {
+				add(format!("<{tag}>"));
+			}
+			continue;
+		}
+
+		// Attributes, up to the end of the tag
+		let mut attrs = &s[end..];
+		loop {
+			attrs = attrs.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+			if attrs.is_empty() || attrs.starts_with('>') {
+				break;
+			}
+
+			let n = attrs
+				.find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+				.unwrap_or(attrs.len());
+			let name = attrs[..n].to_ascii_lowercase();
+			attrs = attrs[n..].trim_start();
+
+			let mut value = "";
+			if let Some(v) = attrs.strip_prefix('=') {
+				let v = v.trim_start();
+				let (val, len) = match v.chars().next() {
+					Some(q @ ('"' | '\'')) => {
+						let close = v[1..].find(q).map_or(v.len(), |i| i + 1);
+						(&v[1..close], usize::min(close + 1, v.len()))
+					}
+					_ => {
+						let n = v
+							.find(|c: char| c.is_whitespace() || c == '>')
+							.unwrap_or(v.len());
+						(&v[..n], n)
+					}
+				};
+				value = val;
+				attrs = &v[len..];
+			}
+
+			if n == 0 {
+				// Not a valid attribute name; skip a character to make progress
+				attrs = &attrs[attrs.chars().next().map_or(0, char::len_utf8)..];
+			} else if !allowed_attribute(&tag, &name) {
+				add(format!("the {name} attribute of <{tag}>"));
+			} else if URL_ATTRIBUTES.contains(&name.as_str()) && !allowed_scheme(value) {
+				add(format!("the URL {value}"));
+			}
+		}
+	}
+}
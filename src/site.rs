@@ -0,0 +1,783 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	cmp::Ordering,
+	collections::{
+		BTreeMap,
+		HashMap,
+	},
+	path::{
+		Component,
+		Path,
+		PathBuf,
+	},
+};
+
+use askama::Template;
+use log::warn;
+use normpath::BasePath;
+use percent_encoding::{
+	utf8_percent_encode,
+	AsciiSet,
+	CONTROLS,
+};
+use pulldown_cmark::{
+	Event,
+	Options,
+	Parser,
+	Tag,
+	TagEnd,
+};
+use serde::{
+	Deserialize,
+	Deserializer,
+};
+use serde_yaml::Value;
+
+use crate::{
+	date::Timestamp,
+	taxonomy,
+	RenderOptions,
+};
+
+/// Characters that must be escaped in a path segment of a relative URL.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+	.add(b' ')
+	.add(b'"')
+	.add(b'#')
+	.add(b'%')
+	.add(b'<')
+	.add(b'>')
+	.add(b'?')
+	.add(b'`')
+	.add(b'{')
+	.add(b'}');
+
+/// Front matter keys that affect the layout of a directory.
+///
+/// The keys are read leniently, so that a value of an unexpected type doesn't
+/// discard the rest of the front matter, such as `draft`.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+	#[serde(deserialize_with = "scalar")]
+	pub title: Option<String>,
+	#[serde(deserialize_with = "integer")]
+	pub weight: Option<i64>,
+	#[serde(deserialize_with = "integer")]
+	pub order: Option<i64>,
+	/// Checked by [`FrontMatter::unpublished`], which reports invalid values.
+	pub draft: Value,
+	#[serde(deserialize_with = "scalar")]
+	pub publish_date: Option<String>,
+	#[serde(deserialize_with = "scalar")]
+	pub expiry_date: Option<String>,
+	/// Any other key, for taxonomies.
+	#[serde(flatten)]
+	pub rest: BTreeMap<String, Value>,
+}
+
+impl FrontMatter {
+	/// Returns the reason the page shouldn't be published at `now`, if any.
+	pub fn unpublished(&self, now: Timestamp) -> Result<Option<&'static str>, String> {
+		let draft = match &self.draft {
+			Value::Null => false,
+			Value::Bool(b) => *b,
+			Value::String(s) if s.eq_ignore_ascii_case("true") => true,
+			Value::String(s) if s.eq_ignore_ascii_case("false") => false,
+			_ => return Err("draft: expected true or false".into()),
+		};
+		if draft {
+			return Ok(Some("it is a draft"));
+		}
+
+		let date = |key: &str, val: &Option<String>| {
+			val.as_deref()
+				.map(Timestamp::parse)
+				.transpose()
+				.map_err(|e| format!("{key}: {e}"))
+		};
+
+		if date("publish_date", &self.publish_date)?.is_some_and(|t| t > now) {
+			return Ok(Some("its publish date is in the future"));
+		}
+		if date("expiry_date", &self.expiry_date)?.is_some_and(|t| t <= now) {
+			return Ok(Some("it has expired"));
+		}
+
+		Ok(None)
+	}
+}
+
+/// Reads a string, number or boolean as a string.
+fn scalar<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+	Ok(match Value::deserialize(d)? {
+		Value::String(s) => Some(s),
+		Value::Number(n) => Some(n.to_string()),
+		Value::Bool(b) => Some(b.to_string()),
+		_ => None,
+	})
+}
+
+/// Reads an integer, or a string containing one.
+fn integer<'de, D: Deserializer<'de>>(d: D) -> Result<Option<i64>, D::Error> {
+	Ok(match Value::deserialize(d)? {
+		Value::Number(n) => n.as_i64(),
+		Value::String(s) => s.trim().parse().ok(),
+		_ => None,
+	})
+}
+
+pub struct Page {
+	/// Path to the source file.
+	pub src: PathBuf,
+	/// Path to the source file, relative to the input directory.
+	pub rel: PathBuf,
+	/// Path to the generated file, relative to the output directory.
+	pub out: PathBuf,
+	/// The title from the front matter, the first heading or the file name.
+	pub title: String,
+	/// The terms of each configured taxonomy, in the same order.
+	pub terms: Vec<Vec<String>>,
+	weight: Option<i64>,
+}
+
+impl Page {
+	/// `body` is the Markdown source without the front matter, parsed with
+	/// `options` to find the first heading.
+	pub fn new(
+		src: PathBuf,
+		rel: PathBuf,
+		fm: FrontMatter,
+		body: &str,
+		options: Options,
+		ro: &RenderOptions,
+	) -> Self {
+		let mut out = rel.clone();
+		let is_index = rel
+			.file_stem()
+			.is_some_and(|s| s.eq_ignore_ascii_case("index"));
+		if ro.clean_urls && !is_index {
+			// foo.md -> foo/index.html
+			out.set_extension("");
+			out.push("index.html");
+		} else {
+			out.set_extension("html");
+		}
+
+		let title = fm
+			.title
+			.or_else(|| first_heading(body, options))
+			.unwrap_or_else(|| {
+				rel.file_stem()
+					.unwrap_or_default()
+					.to_string_lossy()
+					.into_owned()
+			});
+
+		Self {
+			src,
+			rel,
+			out,
+			title,
+			terms: ro
+				.taxonomy
+				.iter()
+				.map(|key| taxonomy::terms(fm.rest.get(key)))
+				.collect(),
+			weight: fm.weight.or(fm.order),
+		}
+	}
+
+	/// Returns how many directories deeper the output is than the source.
+	pub fn depth_change(&self) -> usize {
+		self.out.components().count() - self.rel.components().count()
+	}
+
+	/// Returns 0 for `index.md`, 1 for `readme.md` and `None` for other pages.
+	fn index_rank(&self) -> Option<u8> {
+		let stem = self.rel.file_stem()?.to_str()?;
+		if stem.eq_ignore_ascii_case("index") {
+			Some(0)
+		} else if stem.eq_ignore_ascii_case("readme") {
+			Some(1)
+		} else {
+			None
+		}
+	}
+}
+
+/// Returns the text of the first heading in a Markdown document.
+fn first_heading(md: &str, options: Options) -> Option<String> {
+	let mut title = String::new();
+	let mut in_heading = false;
+
+	for e in Parser::new_ext(md, options) {
+		match e {
+			Event::Start(Tag::Heading { .. }) => in_heading = true,
+			Event::End(TagEnd::Heading(_)) => break,
+			Event::Text(s) | Event::Code(s) if in_heading => title.push_str(&s),
+			_ => (),
+		}
+	}
+
+	let title = title.trim();
+	(!title.is_empty()).then(|| title.to_owned())
+}
+
+/// A node in the navigation tree; either a directory or an entry in the
+/// summary file.
+#[derive(Default)]
+struct Node {
+	/// The directory or file name, used for sorting.
+	name: String,
+	title: String,
+	/// The page this node links to; a directory's index page.
+	page: Option<usize>,
+	children: Vec<Node>,
+}
+
+impl Node {
+	fn dir(&mut self, name: &str) -> &mut Self {
+		let pos = self
+			.children
+			.iter()
+			.position(|n| n.page.is_none() && n.name == name);
+
+		let i = pos.unwrap_or_else(|| {
+			self.children.push(Self {
+				name: name.to_owned(),
+				title: name.to_owned(),
+				..Self::default()
+			});
+			self.children.len() - 1
+		});
+
+		&mut self.children[i]
+	}
+
+	/// Picks the index pages and sorts the entries of a directory tree,
+	/// recursively.
+	fn arrange(&mut self, pages: &[Page]) {
+		let index = self
+			.children
+			.iter()
+			.enumerate()
+			.filter_map(|(pos, n)| Some((pages[n.page?].index_rank()?, pos)))
+			.min();
+
+		if let Some((_, pos)) = index {
+			let i = self.children.remove(pos).page;
+			self.page = i;
+			if let Some(i) = i {
+				self.title.clone_from(&pages[i].title);
+			}
+		}
+
+		for n in &mut self.children {
+			if n.page.is_none() {
+				n.arrange(pages);
+			}
+		}
+
+		let weight = |n: &Self| n.page.and_then(|i| pages[i].weight);
+		self.children.sort_by(|a, b| {
+			// Weighted entries go first, in ascending order
+			let by_weight = match (weight(a), weight(b)) {
+				(Some(a), Some(b)) => a.cmp(&b),
+				(Some(_), None) => Ordering::Less,
+				(None, Some(_)) => Ordering::Greater,
+				(None, None) => Ordering::Equal,
+			};
+			by_weight.then_with(|| a.name.cmp(&b.name))
+		});
+	}
+
+	fn flatten(&self, order: &mut Vec<usize>) {
+		order.extend(self.page);
+		for n in &self.children {
+			n.flatten(order);
+		}
+	}
+
+	/// Returns the nodes leading to `page`, starting with `self`.
+	fn find(&self, page: usize) -> Option<Vec<&Self>> {
+		if self.page == Some(page) {
+			return Some(vec![self]);
+		}
+		self.children.iter().find_map(|n| {
+			n.find(page).map(|mut path| {
+				path.insert(0, self);
+				path
+			})
+		})
+	}
+}
+
+pub struct Link {
+	pub title: String,
+	/// Empty for directories without an index page.
+	pub url: String,
+}
+
+/// Navigation variables exposed to the template.
+pub struct PageNav {
+	pub prev: Option<Link>,
+	pub next: Option<Link>,
+	/// Links to the index pages of the parent directories, starting at the
+	/// root.
+	pub breadcrumbs: Vec<Link>,
+}
+
+/// The navigation tree of the whole site, as seen from one page.
+#[derive(Template)]
+#[template(path = "sidebar.html")]
+pub struct Sidebar {
+	entries: Vec<SidebarEntry>,
+}
+
+struct SidebarEntry {
+	link: Link,
+	/// Whether this is the current page.
+	current: bool,
+	/// Whether the current page is under this entry.
+	open: bool,
+	children: Option<Sidebar>,
+}
+
+pub struct Site {
+	pub pages: Vec<Page>,
+	root: Node,
+	/// Page indices in reading order.
+	order: Vec<usize>,
+	/// The position of each page in `order`, if it's in the navigation tree.
+	position: Vec<Option<usize>>,
+	/// Pages by their normalized source paths.
+	by_src: HashMap<PathBuf, usize>,
+	/// Pages by their file names, for wikilinks.
+	names: Names,
+	clean_urls: bool,
+}
+
+/// Finds files by their names, as wikilinks such as `[[Page Name]]` do.
+///
+/// Names are matched case insensitively against file names without the
+/// extension. Names with a `/` are matched against the end of the path, which
+/// disambiguates files with the same name.
+pub struct Names {
+	/// Indices by lowercase file names without the extension.
+	by_stem: HashMap<String, Vec<usize>>,
+	/// The lowercase paths without the extension, joined with `/`.
+	paths: Vec<String>,
+	/// The paths for messages.
+	display: Vec<String>,
+}
+
+impl Names {
+	pub fn new<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Self {
+		let mut names = Self {
+			by_stem: HashMap::new(),
+			paths: Vec::new(),
+			display: Vec::new(),
+		};
+		for (i, p) in paths.into_iter().enumerate() {
+			let p = p.as_ref();
+			let stem = p.file_stem().unwrap_or_default();
+			names
+				.by_stem
+				.entry(stem.to_string_lossy().to_lowercase())
+				.or_default()
+				.push(i);
+			names.paths.push(
+				p.with_extension("")
+					.components()
+					.filter(|c| matches!(c, Component::Normal(_)))
+					.map(|c| c.as_os_str().to_string_lossy().to_lowercase())
+					.collect::<Vec<_>>()
+					.join("/"),
+			);
+			names.display.push(p.display().to_string());
+		}
+		names
+	}
+
+	/// Returns the index of the path `name` refers to.
+	pub fn find(&self, name: &str) -> Result<usize, String> {
+		let name = name.trim().trim_start_matches('/').to_lowercase();
+		let mut found = self.named(&name);
+		if found.is_empty() {
+			// The extension is optional
+			if let Some((stem, _)) = name.rsplit_once('.').filter(|(s, _)| !s.is_empty()) {
+				found = self.named(stem);
+			}
+		}
+
+		match found[..] {
+			[] => Err(format!("no page is named `{name}`")),
+			[i] => Ok(i),
+			_ => {
+				let mut paths = found
+					.iter()
+					.map(|&i| self.display[i].as_str())
+					.collect::<Vec<_>>();
+				paths.sort();
+				Err(format!(
+					"the name `{name}` is ambiguous; it matches {}",
+					paths.join(", ")
+				))
+			}
+		}
+	}
+
+	fn named(&self, name: &str) -> Vec<usize> {
+		let stem = name.rsplit_once('/').map_or(name, |(_, stem)| stem);
+		self.by_stem
+			.get(stem)
+			.into_iter()
+			.flatten()
+			.copied()
+			.filter(|&i| {
+				let path = &self.paths[i];
+				!name.contains('/') || *path == name || path.ends_with(&format!("/{name}"))
+			})
+			.collect()
+	}
+}
+
+impl Site {
+	/// Lays out the pages according to the directory hierarchy.
+	pub fn new(pages: Vec<Page>, clean_urls: bool) -> Self {
+		let mut root = Node::default();
+
+		for (i, p) in pages.iter().enumerate() {
+			let mut dir = &mut root;
+			for c in p.rel.parent().into_iter().flat_map(Path::components) {
+				if let Component::Normal(name) = c {
+					dir = dir.dir(&name.to_string_lossy());
+				}
+			}
+			dir.children.push(Node {
+				name: p
+					.rel
+					.file_name()
+					.unwrap_or_default()
+					.to_string_lossy()
+					.into_owned(),
+				title: p.title.clone(),
+				page: Some(i),
+				children: Vec::new(),
+			});
+		}
+
+		root.arrange(&pages);
+		Self::with_tree(pages, root, clean_urls)
+	}
+
+	/// Lays out the pages according to a summary file.
+	///
+	/// The summary is a Markdown document with a (nested) list of links to the
+	/// pages. `summary` is the path of the file relative to the input
+	/// directory. Pages not linked from the summary are left out of the
+	/// navigation.
+	pub fn with_summary(pages: Vec<Page>, summary: &Path, source: &str, clean_urls: bool) -> Self {
+		let by_path = pages
+			.iter()
+			.enumerate()
+			.map(|(i, p)| (p.rel.as_path(), i))
+			.collect::<HashMap<_, _>>();
+
+		let base = summary.parent().unwrap_or(Path::new(""));
+		let mut stack = vec![Node::default()];
+		let mut in_link = false;
+
+		for e in Parser::new(source) {
+			match e {
+				Event::Start(Tag::Item) => stack.push(Node::default()),
+				Event::End(TagEnd::Item) if stack.len() > 1 => {
+					let n = stack.pop().unwrap();
+					stack.last_mut().unwrap().children.push(n);
+				}
+				Event::Start(Tag::Link { dest_url, .. }) if stack.len() > 1 => {
+					let n = stack.last_mut().unwrap();
+					if n.page.is_some() {
+						continue;
+					}
+					in_link = true;
+
+					let url = percent_encoding::percent_decode_str(&dest_url).decode_utf8_lossy();
+					match by_path.get(normalize(&base.join(&*url)).as_path()) {
+						Some(&i) => n.page = Some(i),
+						None => warn!(
+							"{}: the link {} does not point to a page",
+							summary.display(),
+							dest_url
+						),
+					}
+				}
+				Event::End(TagEnd::Link) => in_link = false,
+				Event::Text(s) | Event::Code(s) if stack.len() > 1 => {
+					let n = stack.last_mut().unwrap();
+					// Ignore the text following the link
+					if in_link || n.page.is_none() {
+						n.title.push_str(&s);
+					}
+				}
+				_ => (),
+			}
+		}
+
+		let mut root = stack.swap_remove(0);
+		fill_titles(&mut root, &pages);
+		Self::with_tree(pages, root, clean_urls)
+	}
+
+	fn with_tree(pages: Vec<Page>, root: Node, clean_urls: bool) -> Self {
+		let mut order = Vec::with_capacity(pages.len());
+		root.flatten(&mut order);
+
+		let mut position = vec![None; pages.len()];
+		for (pos, &i) in order.iter().enumerate() {
+			if position[i].is_none() {
+				position[i] = Some(pos);
+			}
+		}
+
+		let by_src = pages
+			.iter()
+			.enumerate()
+			.filter_map(|(i, p)| {
+				let src = BasePath::new(&p.src).ok()?.normalize().ok()?;
+				Some((src.into_path_buf(), i))
+			})
+			.collect();
+
+		let names = Names::new(pages.iter().map(|p| &p.rel));
+
+		Self {
+			pages,
+			root,
+			order,
+			position,
+			by_src,
+			names,
+			clean_urls,
+		}
+	}
+
+	/// Finds the page a wikilink such as `[[Page Name]]` points to, by its
+	/// path relative to the input directory; see [`Names`].
+	pub fn find_by_name(&self, name: &str) -> Result<usize, String> {
+		self.names.find(name)
+	}
+
+	/// Returns the page with the given normalized source path.
+	pub fn find(&self, src: &Path) -> Option<usize> {
+		self.by_src.get(src).copied()
+	}
+
+	/// Returns a URL pointing to the page `to` from the page `from`.
+	pub fn url(&self, from: usize, to: usize) -> String {
+		page_url(&self.pages[from].out, &self.pages[to].out, self.clean_urls)
+	}
+
+	/// Returns an absolute URL to a page, `/` being the output directory.
+	pub fn root_url(&self, page: usize) -> String {
+		let url = relative_url(Path::new("_"), &self.pages[page].out);
+		match url.strip_suffix("index.html") {
+			Some(dir) if self.clean_urls && (dir.is_empty() || dir.ends_with('/')) => {
+				format!("/{dir}")
+			}
+			_ => format!("/{url}"),
+		}
+	}
+
+	fn link(&self, from: usize, to: usize) -> Link {
+		Link {
+			title: self.pages[to].title.clone(),
+			url: self.url(from, to),
+		}
+	}
+
+	fn node_link(&self, from: usize, n: &Node) -> Link {
+		Link {
+			title: n.title.clone(),
+			url: n.page.map(|i| self.url(from, i)).unwrap_or_default(),
+		}
+	}
+
+	/// Returns every page index once, in reading order, followed by the pages
+	/// left out of the navigation.
+	pub fn reading_order(&self) -> Vec<usize> {
+		let mut order = (0..self.pages.len())
+			.filter_map(|i| self.position[i].map(|pos| (pos, i)))
+			.collect::<Vec<_>>();
+		order.sort_unstable();
+		let mut order = order.into_iter().map(|(_, i)| i).collect::<Vec<_>>();
+		order.extend((0..self.pages.len()).filter(|&i| self.position[i].is_none()));
+		order
+	}
+
+	pub fn nav(&self, page: usize) -> PageNav {
+		let Some(pos) = self.position[page] else {
+			return PageNav {
+				prev: None,
+				next: None,
+				breadcrumbs: Vec::new(),
+			};
+		};
+
+		let prev = pos
+			.checked_sub(1)
+			.map(|pos| self.link(page, self.order[pos]));
+		let next = self.order.get(pos + 1).map(|&i| self.link(page, i));
+
+		let mut path = self.root.find(page).unwrap_or_default();
+		path.pop();
+
+		let breadcrumbs = path
+			.into_iter()
+			.enumerate()
+			// The root has no name worth showing
+			.filter(|(depth, n)| *depth > 0 || n.page.is_some())
+			.map(|(_, n)| self.node_link(page, n))
+			.collect();
+
+		PageNav {
+			prev,
+			next,
+			breadcrumbs,
+		}
+	}
+
+	pub fn sidebar(&self, page: usize) -> Sidebar {
+		let mut entries = Vec::with_capacity(self.root.children.len() + 1);
+		if let Some(i) = self.root.page {
+			entries.push(SidebarEntry {
+				link: self.link(page, i),
+				current: i == page,
+				open: false,
+				children: None,
+			});
+		}
+
+		entries.extend(
+			self.root
+				.children
+				.iter()
+				.map(|n| self.sidebar_entry(n, page)),
+		);
+
+		Sidebar { entries }
+	}
+
+	fn sidebar_entry(&self, n: &Node, page: usize) -> SidebarEntry {
+		let children = (!n.children.is_empty()).then(|| Sidebar {
+			entries: n
+				.children
+				.iter()
+				.map(|n| self.sidebar_entry(n, page))
+				.collect(),
+		});
+
+		let current = n.page == Some(page);
+		let open = children
+			.as_ref()
+			.is_some_and(|x| x.entries.iter().any(|e| e.current || e.open));
+
+		SidebarEntry {
+			link: self.node_link(page, n),
+			current,
+			open,
+			children,
+		}
+	}
+}
+
+/// Uses page titles for summary entries with no link text.
+fn fill_titles(n: &mut Node, pages: &[Page]) {
+	let title = n.title.trim();
+	if title.is_empty() {
+		if let Some(i) = n.page {
+			n.title.clone_from(&pages[i].title);
+		}
+	} else if title.len() != n.title.len() {
+		n.title = title.to_owned();
+	}
+
+	for n in &mut n.children {
+		fill_titles(n, pages);
+	}
+}
+
+/// Turns `s` into a lowercase, URL friendly identifier.
+///
+/// Alphanumerics, `-` and `_` are kept, whitespace is replaced with `-` and
+/// anything else is removed.
+pub fn slug(s: &str) -> String {
+	let mut buf = String::with_capacity(s.len());
+	for c in s.trim().chars() {
+		if c.is_alphanumeric() || c == '_' || c == '-' {
+			buf.extend(c.to_lowercase());
+		} else if c.is_whitespace() {
+			buf.push('-');
+		}
+	}
+	buf
+}
+
+/// Resolves `.` and `..` components without touching the file system.
+fn normalize(p: &Path) -> PathBuf {
+	let mut buf = PathBuf::new();
+	for c in p.components() {
+		match c {
+			Component::CurDir => (),
+			Component::ParentDir => {
+				buf.pop();
+			}
+			Component::Normal(s) => buf.push(s),
+			Component::RootDir | Component::Prefix(_) => (),
+		}
+	}
+	buf
+}
+
+/// Like [relative_url], but drops the trailing `index.html` if `clean` is
+/// set.
+pub fn page_url(from: &Path, to: &Path, clean: bool) -> String {
+	let url = relative_url(from, to);
+	match url.strip_suffix("index.html") {
+		Some("") if clean => "./".to_owned(),
+		Some(dir) if clean && dir.ends_with('/') => dir.to_owned(),
+		_ => url,
+	}
+}
+
+/// Returns a URL pointing to `to` from the file `from`.
+///
+/// Both paths must be relative to the same directory.
+pub fn relative_url(from: &Path, to: &Path) -> String {
+	let from = from
+		.parent()
+		.map(|p| p.components().collect::<Vec<_>>())
+		.unwrap_or_default();
+	let to = to.components().collect::<Vec<_>>();
+
+	let common = from
+		.iter()
+		.zip(&to[..to.len().saturating_sub(1)])
+		.take_while(|(a, b)| a == b)
+		.count();
+
+	let mut url = "../".repeat(from.len() - common);
+	for (i, c) in to[common..].iter().enumerate() {
+		if i > 0 {
+			url.push('/');
+		}
+		url.extend(utf8_percent_encode(
+			&c.as_os_str().to_string_lossy(),
+			PATH_SEGMENT,
+		));
+	}
+
+	url
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	borrow::Cow,
+	fs,
+	hash::{
+		Hash,
+		Hasher,
+	},
+	path::Path,
+};
+
+use base64::{
+	engine::general_purpose::STANDARD,
+	Engine,
+};
+use indexmap::IndexSet;
+use log::warn;
+use serde::{
+	Deserialize,
+	Deserializer,
+};
+use sha2::{
+	Digest,
+	Sha384,
+};
+
+use crate::{
+	diagram::MERMAID_SCRIPT,
+	link,
+};
+
+/// The stylesheet added by `--normalize-css`.
+pub const NORMALIZE_CSS: &str = "https://unpkg.com/normalize.css@8.0.1/normalize.css";
+
+/// The stylesheet added by `--sakura-css`.
+pub const SAKURA_CSS: &str = "https://cdn.jsdelivr.net/npm/sakura.css@1.5.0/css/sakura.css";
+
+/// The integrity hashes of the stylesheets and scripts mars adds by itself.
+///
+/// Each is the `sha384` of the file served at the pinned URL, as computed by
+/// `curl -s URL | openssl dgst -sha384 -binary | openssl base64 -A`. A URL
+/// without an entry is linked without a hash.
+const BUILTIN: &[(&str, &str)] = &[];
+
+/// A stylesheet or a script.
+///
+/// In the front matter, it's either a URL or a map with the `href` and
+/// `integrity` keys. Resources are told apart by their URLs only.
+#[derive(Clone, Debug)]
+pub struct Resource<'a> {
+	pub href: Cow<'a, str>,
+	/// The Subresource Integrity hash, such as `sha384-...`.
+	pub integrity: Option<Cow<'a, str>>,
+}
+
+impl<'a> From<&'a str> for Resource<'a> {
+	fn from(href: &'a str) -> Self {
+		Self {
+			href: Cow::Borrowed(href),
+			integrity: None,
+		}
+	}
+}
+
+impl Resource<'static> {
+	/// Returns one of the resources mars adds by itself, with its integrity
+	/// hash.
+	pub fn builtin(href: &'static str) -> Self {
+		debug_assert!([NORMALIZE_CSS, SAKURA_CSS, MERMAID_SCRIPT].contains(&href));
+		Self {
+			href: Cow::Borrowed(href),
+			integrity: BUILTIN
+				.iter()
+				.find(|(url, _)| *url == href)
+				.map(|(_, hash)| Cow::Borrowed(*hash)),
+		}
+	}
+}
+
+impl PartialEq for Resource<'_> {
+	fn eq(&self, other: &Self) -> bool {
+		self.href == other.href
+	}
+}
+
+impl Eq for Resource<'_> {}
+
+impl Hash for Resource<'_> {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.href.hash(state);
+	}
+}
+
+impl<'de> Deserialize<'de> for Resource<'_> {
+	fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+		#[derive(Deserialize)]
+		#[serde(untagged)]
+		enum Repr {
+			Url(String),
+			Map {
+				#[serde(alias = "src")]
+				href: String,
+				integrity: Option<String>,
+			},
+		}
+
+		Ok(match Repr::deserialize(d)? {
+			Repr::Url(href) => Self {
+				href: Cow::Owned(href),
+				integrity: None,
+			},
+			Repr::Map { href, integrity } => Self {
+				href: Cow::Owned(href),
+				integrity: integrity.map(Cow::Owned),
+			},
+		})
+	}
+}
+
+/// Computes the `sha384` integrity hash of a file.
+fn hash_file(path: &Path) -> std::io::Result<String> {
+	let data = fs::read(path)?;
+	Ok(format!("sha384-{}", STANDARD.encode(Sha384::digest(&data))))
+}
+
+/// Fills in the integrity hashes of the resources that are local files,
+/// relative to the output file `out` as the browser resolves them, or to the
+/// current directory if the output is not a file.
+///
+/// Resources that already have a hash, URLs with a scheme and absolute paths
+/// are left alone.
+pub fn hash_local<'a>(set: IndexSet<Resource<'a>>, out: Option<&Path>) -> IndexSet<Resource<'a>> {
+	set.into_iter()
+		.map(|mut r| {
+			let path = r.href.split(['?', '#']).next().unwrap_or_default();
+			let is_local = !path.is_empty() && !path.starts_with('/') && !link::has_scheme(path);
+
+			if r.integrity.is_none() && is_local {
+				let dir = out.and_then(Path::parent).unwrap_or(Path::new(""));
+				let file = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+				let file = dir.join(&*file);
+				match hash_file(&file) {
+					Ok(hash) => r.integrity = Some(Cow::Owned(hash)),
+					Err(e) => warn!(
+						"failed to compute the integrity hash of {}: {}",
+						file.display(),
+						e
+					),
+				}
+			}
+			r
+		})
+		.collect()
+}
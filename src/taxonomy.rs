@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	collections::BTreeMap,
+	fmt::Write,
+	path::PathBuf,
+};
+
+use log::warn;
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::site::{
+	page_url,
+	slug,
+	Link,
+	Page,
+};
+
+/// Validates the name of a taxonomy given on the command line.
+///
+/// The name is used as a directory name, so only ASCII alphanumerics, `-` and
+/// `_` are allowed.
+pub fn parse_key(s: &str) -> Result<String, String> {
+	if s.is_empty() {
+		Err("the name of a taxonomy can't be empty".into())
+	} else if s
+		.bytes()
+		.all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+	{
+		Ok(s.to_owned())
+	} else {
+		Err(format!(
+			"invalid taxonomy name `{s}`: only alphanumerics, '-' and '_' are allowed"
+		))
+	}
+}
+
+/// Reads the terms of a taxonomy from a front matter value, which can be a
+/// single term or a list of them.
+pub fn terms(val: Option<&Value>) -> Vec<String> {
+	fn term(v: &Value) -> Option<String> {
+		match v {
+			Value::String(s) => Some(s.trim().to_owned()),
+			Value::Number(n) => Some(n.to_string()),
+			Value::Bool(b) => Some(b.to_string()),
+			_ => None,
+		}
+	}
+
+	match val {
+		None | Some(Value::Null) => Vec::new(),
+		Some(Value::Sequence(xs)) => xs.iter().filter_map(term).collect(),
+		Some(v) => term(v).into_iter().collect(),
+	}
+}
+
+struct Term {
+	name: String,
+	pages: Vec<usize>,
+}
+
+pub struct Taxonomy {
+	pub key: String,
+	/// Terms by their slugs.
+	terms: BTreeMap<String, Term>,
+	clean_urls: bool,
+}
+
+impl Taxonomy {
+	/// Collects the terms of the `n`th configured taxonomy.
+	pub fn collect(key: &str, n: usize, pages: &[Page], clean_urls: bool) -> Self {
+		let mut terms = BTreeMap::<String, Term>::new();
+
+		for (i, p) in pages.iter().enumerate() {
+			for name in &p.terms[n] {
+				let s = slug(name);
+				if s.is_empty() {
+					warn!(
+						"{}: ignoring the {key} term `{name}`: it has no alphanumeric characters",
+						p.rel.display()
+					);
+					continue;
+				}
+
+				let term = terms.entry(s).or_insert_with(|| Term {
+					name: name.clone(),
+					pages: Vec::new(),
+				});
+				if !term.pages.contains(&i) {
+					term.pages.push(i);
+				}
+			}
+		}
+
+		for term in terms.values_mut() {
+			term.pages.sort_by(|&a, &b| {
+				pages[a]
+					.title
+					.cmp(&pages[b].title)
+					.then_with(|| pages[a].out.cmp(&pages[b].out))
+			});
+		}
+
+		Self {
+			key: key.to_owned(),
+			terms,
+			clean_urls,
+		}
+	}
+
+	/// The path of the term index, relative to the output directory.
+	pub fn index_path(&self) -> PathBuf {
+		[&self.key, "index.html"].iter().collect()
+	}
+
+	fn term_path(&self, slug: &str) -> PathBuf {
+		if self.clean_urls {
+			[&self.key, slug, "index.html"].iter().collect()
+		} else {
+			[&self.key, &format!("{slug}.html")].iter().collect()
+		}
+	}
+
+	/// Returns links to the term pages of a page.
+	pub fn links(&self, n: usize, page: &Page) -> Vec<Link> {
+		page.terms[n]
+			.iter()
+			.filter_map(|name| {
+				let s = slug(name);
+				self.terms.get(&s).map(|term| Link {
+					title: term.name.clone(),
+					url: page_url(&page.out, &self.term_path(&s), self.clean_urls),
+				})
+			})
+			.collect()
+	}
+
+	/// Returns the path and the Markdown source of every page to generate, the
+	/// term index being the first one.
+	pub fn generate(&self, pages: &[Page]) -> Vec<(PathBuf, String)> {
+		let mut docs = Vec::with_capacity(self.terms.len() + 1);
+
+		let index = self.index_path();
+		let mut md = front_matter(&self.key);
+		for (s, term) in &self.terms {
+			let _ = writeln!(
+				md,
+				"- [{}](<{}>) ({})",
+				escape(&term.name),
+				page_url(&index, &self.term_path(s), self.clean_urls),
+				term.pages.len()
+			);
+		}
+		docs.push((index, md));
+
+		for (s, term) in &self.terms {
+			let path = self.term_path(s);
+			let mut md = front_matter(&term.name);
+			for &i in &term.pages {
+				let _ = writeln!(
+					md,
+					"- [{}](<{}>)",
+					escape(&pages[i].title),
+					page_url(&path, &pages[i].out, self.clean_urls)
+				);
+			}
+			docs.push((path, md));
+		}
+
+		docs
+	}
+}
+
+/// Links to the terms of one taxonomy, exposed to the template.
+pub struct PageTerms {
+	pub key: String,
+	pub terms: Vec<Link>,
+}
+
+fn front_matter(title: &str) -> String {
+	#[derive(Serialize)]
+	struct Md<'a> {
+		title: &'a str,
+	}
+
+	let yaml = serde_yaml::to_string(&Md { title }).unwrap_or_default();
+	format!("---\n{yaml}---\n\n# {}\n\n", escape(title))
+}
+
+/// Escapes ASCII punctuation so that `s` is rendered as is in Markdown.
+fn escape(s: &str) -> String {
+	let mut buf = String::with_capacity(s.len());
+	for c in s.chars() {
+		if c.is_ascii_punctuation() {
+			buf.push('\\');
+		}
+		buf.push(c);
+	}
+	buf
+}
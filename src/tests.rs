@@ -60,3 +60,830 @@ fn test_has_hidden() {
 		);
 	}
 }
+
+#[test]
+fn test_relative_url() {
+	let tests = [
+		("a.html", "b.html", "b.html"),
+		("a.html", "a.html", "a.html"),
+		("a.html", "x/b.html", "x/b.html"),
+		("x/a.html", "b.html", "../b.html"),
+		("x/y/a.html", "x/b.html", "../b.html"),
+		("x/y/a.html", "z/w/b.html", "../../z/w/b.html"),
+		("x/a.html", "x/y/b.html", "y/b.html"),
+		("a.html", "my docs/b c.html", "my%20docs/b%20c.html"),
+		("a.html", "x#y/b?.html", "x%23y/b%3F.html"),
+	];
+
+	for (from, to, expected) in tests {
+		let got = site::relative_url(Path::new(from), Path::new(to));
+		assert_eq!(expected, got, "\nfrom: {from}\nto: {to}");
+	}
+}
+
+#[test]
+fn test_slug() {
+	let tests = [
+		("Rust", "rust"),
+		("  Getting Started ", "getting-started"),
+		("C++ stuff", "c-stuff"),
+		("snake_case-and-kebab", "snake_case-and-kebab"),
+		("Ünïcode Wörds", "ünïcode-wörds"),
+		("?!", ""),
+	];
+
+	for (s, expected) in tests {
+		assert_eq!(expected, site::slug(s), "\ninput: {s}");
+	}
+}
+
+#[test]
+fn test_parse_timestamp() {
+	let tests = [
+		("1970-01-01", 0),
+		("1970-01-02", 86400),
+		("2000-03-01", 951868800),
+		("2024-02-29", 1709164800),
+		("2024-02-29T10:30", 1709164800 + 10 * 3600 + 30 * 60),
+		("2024-02-29 10:30:15", 1709164800 + 10 * 3600 + 30 * 60 + 15),
+		(
+			"2024-02-29T10:30:15.250Z",
+			1709164800 + 10 * 3600 + 30 * 60 + 15,
+		),
+		("2024-02-29T10:30:00+02:00", 1709164800 + 8 * 3600 + 30 * 60),
+		("2024-02-29T10:30:00-0130", 1709164800 + 12 * 3600),
+		("1969-12-31", -86400),
+	];
+
+	for (s, expected) in tests {
+		assert_eq!(Ok(Timestamp(expected)), Timestamp::parse(s), "\ninput: {s}");
+	}
+
+	let invalid = [
+		"",
+		"2024",
+		"2024-1-1",
+		"2023-02-29",
+		"2024-13-01",
+		"2024-01-01T25:00",
+		"2024-01-01T10",
+		"2024-01-01T10:00+2",
+		"2024-01-01T10:00+0é1",
+		"2024-01-01T10:00-é0",
+		"yesterday",
+	];
+
+	for s in invalid {
+		assert!(
+			Timestamp::parse(s).is_err(),
+			"\nexpected an error\ninput: {s}"
+		);
+	}
+}
+
+#[test]
+fn test_minify_html() {
+	let tests = [
+		("<p>a  \n b</p>\n<p> c </p>", "<p>a b</p><p>c</p>"),
+		(
+			"<pre><code>a  b\n  c</code></pre>",
+			"<pre><code>a  b\n  c</code></pre>",
+		),
+		("<p>a<!-- x --> <em>b</em></p>", "<p>a <em>b</em></p>"),
+		(
+			"<a href=\"x.html\" title=\"a b\" class=\"\">x</a>",
+			"<a href=x.html title=\"a b\" class=\"\">x</a>",
+		),
+		("<img src=\"a/b.png\" />", "<img src=a/b.png />"),
+		(
+			"<style>\n\ta > b { color: red; }\n</style>",
+			"<style>a>b{color:red}</style>",
+		),
+		(
+			"<script>\n\t// c\n\tlet a = \"x  y\";\n\tf(a)\n\tg()\n</script>",
+			"<script>let a=\"x  y\";f(a)\ng()</script>",
+		),
+		(
+			"<script type=\"text/x-template\"> a  b </script>",
+			"<script type=text/x-template> a  b </script>",
+		),
+	];
+
+	for (s, expected) in tests {
+		let mut buf = String::new();
+		minify::html(s, &mut buf);
+		assert_eq!(expected, buf, "\ninput: {s}");
+	}
+}
+
+#[test]
+fn test_xhtml_convert() {
+	let tests = [
+		("<p>a<br>b</p>", "<p>a<br />b</p>"),
+		("<p>a<p>b", "<p>a</p><p>b</p>"),
+		("<ul><li>a<li>b</ul>", "<ul><li>a</li><li>b</li></ul>"),
+		("<span>a</div>b", "<span>ab</span>"),
+		("&copy; &amp; &bogus; &", "© &amp; &amp;bogus; &amp;"),
+		(
+			"<input type=checkbox checked disabled>",
+			"<input type=\"checkbox\" checked=\"checked\" disabled=\"disabled\" />",
+		),
+		("<a href='x?a=1&amp;b=\"2\"'>x</a>", "<a href=\"x?a=1&amp;b=&quot;2&quot;\">x</a>"),
+		(
+			"<svg viewBox=\"0 0 1 1\"><path d=\"M0\"/></svg>",
+			"<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" viewBox=\"0 0 1 1\"><path d=\"M0\" /></svg>",
+		),
+		(
+			"<script>a < b</script><style>p{}</style>",
+			"<script>//<![CDATA[\na < b\n//]]></script><style>p{}</style>",
+		),
+	];
+
+	for (s, expected) in tests {
+		assert_eq!(expected, xhtml::convert(s), "\ninput: {s}");
+	}
+}
+
+#[test]
+fn test_file_anchor() {
+	let tests = [
+		("intro.md", "intro.md"),
+		("docs/Getting Started.md", "getting-started.md"),
+		("a/b/c_d-e.markdown", "c_d-e.markdown"),
+		("\"<>.md", ".md"),
+	];
+
+	for (p, expected) in tests {
+		assert_eq!(expected, file_anchor(Path::new(p)), "\ninput: {p}");
+	}
+
+	let mut seen = HashSet::new();
+	assert_eq!("a", unique_id(&mut seen, "a"));
+	assert_eq!("a-1", unique_id(&mut seen, "a"));
+	assert_eq!("a-2", unique_id(&mut seen, "a"));
+	assert_eq!("a-1-1", unique_id(&mut seen, "a-1"));
+}
+
+#[test]
+fn test_sanitize_refuses_includes() {
+	let dir = std::env::temp_dir().join(format!("mars-test-include-{}", process::id()));
+	fs::create_dir_all(&dir).unwrap();
+	fs::write(dir.join("secret.txt"), "hunter2\n").unwrap();
+	let page = dir.join("page.md");
+	fs::write(&page, "a\n{{< include \"secret.txt\" code >}}\nb\n").unwrap();
+
+	let ctx = |ro| Context {
+		ro,
+		fo: None,
+		format_error_exit: false,
+		minify: false,
+		xhtml: false,
+		validate: false,
+		clean: false,
+	};
+	let mut buf = Buffer::new();
+	let ro = RenderOptions::parse_from(["mars"]);
+	buf.read_file(ctx(&ro), &page).unwrap();
+	assert!(buf.buf.contains("hunter2"));
+
+	let ro = RenderOptions::parse_from(["mars", "--sanitize"]);
+	buf.read_file(ctx(&ro), &page).unwrap();
+	assert!(!buf.buf.contains("hunter2"), "\ngot: {}", buf.buf);
+	assert!(buf.buf.contains("{{< include"));
+
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_front_matter_unpublished() {
+	let now = Timestamp::parse("2024-06-01").unwrap();
+	let tests = [
+		("draft: true", Ok(Some("it is a draft"))),
+		("draft: \"true\"", Ok(Some("it is a draft"))),
+		("draft: false", Ok(None)),
+		("title: 2024\ndraft: true", Ok(Some("it is a draft"))),
+		("weight: \"3\"\ndraft: true", Ok(Some("it is a draft"))),
+		("order: [1]\ndraft: true", Ok(Some("it is a draft"))),
+		(
+			"publish_date: 2024-07-01",
+			Ok(Some("its publish date is in the future")),
+		),
+		("expiry_date: 2024-05-01", Ok(Some("it has expired"))),
+		("publish_date: 2024-05-01", Ok(None)),
+		("title: x", Ok(None)),
+	];
+
+	for (yaml, expected) in tests {
+		let source = format!("---\n{yaml}\n---\n# Hi\n");
+		let (fm, _) = parse_front_matter::<FrontMatter>(&source).expect(yaml);
+		assert_eq!(expected, fm.unpublished(now), "\ninput: {yaml}");
+	}
+
+	let invalid = ["draft: 1", "draft: maybe", "publish_date: 2024"];
+	for yaml in invalid {
+		let source = format!("---\n{yaml}\n---\n");
+		let (fm, _) = parse_front_matter::<FrontMatter>(&source).expect(yaml);
+		assert!(
+			fm.unpublished(now).is_err(),
+			"\nexpected an error\ninput: {yaml}"
+		);
+	}
+
+	let (fm, _) =
+		parse_front_matter::<FrontMatter>("---\ntitle: 2024\nweight: \"3\"\n---\n").unwrap();
+	assert_eq!(Some("2024"), fm.title.as_deref());
+	assert_eq!(Some(3), fm.weight);
+}
+
+#[test]
+fn test_include_parse_range() {
+	let tests = [
+		("5", (5, 5)),
+		("3-10", (3, 10)),
+		("3-", (3, usize::MAX)),
+		("-10", (1, 10)),
+		("-", (1, usize::MAX)),
+		(" 2 - 4 ", (2, 4)),
+		("7-7", (7, 7)),
+	];
+
+	for (s, expected) in tests {
+		let got = include::parse_range(s).unwrap();
+		assert_eq!(expected, got, "\ninput: {s}");
+	}
+
+	let invalid = [
+		"", " ", "0", "0-3", "3-0", "5-3", "a", "1-b", "1-2-3", "-1-",
+	];
+	for s in invalid {
+		assert!(
+			include::parse_range(s).is_err(),
+			"\nexpected an error\ninput: {s}"
+		);
+	}
+}
+
+#[test]
+fn test_include_fence() {
+	// Whether each line is a fence or inside a code block
+	let tests = [
+		("text", false),
+		("```", true),
+		("~~~", true),
+		("{{< include \"a.md\" >}}", true),
+		("```", true),
+		("text", false),
+		("~~~~", true),
+		("```", true),
+		("~~~", true),
+		("~~~~~", true),
+		("text", false),
+		("   ```rust", true),
+		("``` not a closing fence", true),
+		("    ```", true),
+		("```", true),
+		("    ```", false),
+		("``", false),
+		("`````", true),
+		("````", true),
+		("``````  ", true),
+		("text", false),
+	];
+
+	let mut fence = include::Fence::default();
+	for (n, (line, expected)) in tests.into_iter().enumerate() {
+		let got = fence.update(line);
+		assert_eq!(expected, got, "\nline {}: {line}", n + 1);
+	}
+}
+
+#[test]
+fn test_include_directive() {
+	let tests = [
+		("{{< include \"a.md\" >}}", "a.md", None, None),
+		("  {{<include a.md>}}  ", "a.md", None, None),
+		(
+			"{{< include \"my notes.md\" lines=\"3-10\" >}}",
+			"my notes.md",
+			Some((3, 10)),
+			None,
+		),
+		(
+			"{{< include a.rs code lines=-5 >}}",
+			"a.rs",
+			Some((1, 5)),
+			Some(""),
+		),
+		(
+			"{{< include a.txt code=\"toml\" >}}",
+			"a.txt",
+			None,
+			Some("toml"),
+		),
+		("{{< include a.txt code= >}}", "a.txt", None, Some("")),
+	];
+
+	for (line, path, lines, code) in tests {
+		let got = include::Directive::parse(line)
+			.unwrap()
+			.unwrap_or_else(|| panic!("\nnot a directive: {line}"));
+		assert_eq!(
+			(path, lines, code),
+			(got.path.as_str(), got.lines, got.code.as_deref()),
+			"\ninput: {line}"
+		);
+	}
+
+	let not_directives = [
+		"",
+		"text",
+		"{{< includes a.md >}}",
+		"{{< include >}}",
+		"{{< include a.md",
+		"`{{< include a.md >}}`",
+		"{{% include a.md %}}",
+	];
+	for line in not_directives {
+		assert!(
+			matches!(include::Directive::parse(line), Ok(None)),
+			"\nexpected no directive\ninput: {line}"
+		);
+	}
+
+	let invalid = [
+		"{{< include \"a.md >}}",
+		"{{< include a.md lines >}}",
+		"{{< include a.md lines=\"\" >}}",
+		"{{< include a.md lines=0 >}}",
+		"{{< include a.md lines=\"5-3\" >}}",
+		"{{< include a.md foo=1 >}}",
+	];
+	for line in invalid {
+		assert!(
+			include::Directive::parse(line).is_err(),
+			"\nexpected an error\ninput: {line}"
+		);
+	}
+}
+
+#[test]
+fn test_include_take_value() {
+	let tests = [
+		("a b", Some("a"), "b"),
+		("\"a b\" c", Some("a b"), "c"),
+		("\"\"  c", Some(""), "c"),
+		("a", Some("a"), ""),
+		("", None, ""),
+	];
+
+	for (s, expected, rest) in tests {
+		let mut args = s;
+		let got = include::take_value(&mut args).unwrap();
+		assert_eq!((expected, rest), (got.as_deref(), args), "\ninput: {s}");
+	}
+
+	assert!(include::take_value(&mut "\"a b").is_err());
+}
+
+#[test]
+fn test_include_expand() {
+	let dir = std::env::temp_dir().join(format!("mars-test-expand-{}", process::id()));
+	fs::create_dir_all(dir.join("sub")).unwrap();
+	let write = |name: &str, data: &str| fs::write(dir.join(name), data).unwrap();
+	write("lines.txt", "one\ntwo\nthree\nfour\n");
+	write("ticks.md", "a ```` b\n");
+	write(
+		"sub/part.md",
+		"---\ntitle: Part\n---\n\n# Part\n{{< include \"../lines.txt\" lines=\"2\" >}}\n",
+	);
+	write("a.md", "{{< include b.md >}}\n");
+	write("b.md", "{{< include a.md >}}\n");
+
+	let tests = [
+		("{{< include lines.txt lines=\"2-3\" >}}\n", "two\nthree\n"),
+		("{{< include lines.txt lines=3- >}}\n", "three\nfour\n"),
+		("{{< include lines.txt lines=\"-1\" >}}\n", "one\n"),
+		("x\n{{< include sub/part.md >}}\ny\n", "x\n# Part\ntwo\ny\n"),
+		(
+			"- item\n  {{< include lines.txt lines=\"1-2\" >}}\n",
+			"- item\n  one\n  two\n",
+		),
+		(
+			"{{< include ticks.md code >}}\n",
+			"`````md\na ```` b\n`````\n",
+		),
+		(
+			"~~~\n{{< include lines.txt >}}\n~~~\n",
+			"~~~\n{{< include lines.txt >}}\n~~~\n",
+		),
+	];
+
+	let page = dir.join("page.md");
+	for (source, expected) in tests {
+		let got = include::expand(source, &page).unwrap().unwrap();
+		assert_eq!(expected, got, "\ninput: {source}");
+	}
+
+	assert_eq!(None, include::expand("no directives\n", &page).unwrap());
+
+	let invalid = [
+		"{{< include missing.md >}}\n",
+		"{{< include lines.txt lines=5 >}}\n",
+		"{{< include a.md >}}\n",
+	];
+	for source in invalid {
+		assert!(
+			include::expand(source, &page).is_err(),
+			"\nexpected an error\ninput: {source}"
+		);
+	}
+
+	let err = include::expand("{{< include a.md >}}\n", &page)
+		.unwrap_err()
+		.to_string();
+	assert!(err.contains("circular include"), "\ngot: {err}");
+
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_sanitize_clean() {
+	// Each input must lose the given fragment
+	let tests = [
+		("<script>alert(1)</script>", "alert"),
+		("<p>a<script src=\"x.js\"></script></p>", "<script"),
+		("<style>p { color: red }</style>", "color"),
+		("<img src=\"x.png\" onerror=\"alert(1)\">", "onerror"),
+		("<a href=\"#\" onclick=\"alert(1)\">a</a>", "onclick"),
+		(
+			"<a href=\"https://example.com\" target=\"_blank\">a</a>",
+			"target",
+		),
+		("<a href=\"javascript:alert(1)\">a</a>", "javascript:"),
+		("<a href=\" JavaScript:alert(1)\">a</a>", "alert"),
+		("<a href=\"data:text/html,<b>x</b>\">a</a>", "data:"),
+		("<img src=\"data:image/svg+xml,<svg></svg>\">", "data:"),
+		("<img src=\"javascript:alert(1)\">", "javascript:"),
+		("<iframe src=\"https://example.com\"></iframe>", "iframe"),
+		("<p style=\"position: fixed\">a</p>", "style"),
+		("<td style=\"color: red\">a</td>", "color"),
+		("<!-- comment -->", "comment"),
+	];
+
+	for (html, removed) in tests {
+		let got = sanitize::clean(html);
+		assert!(
+			!got.to_ascii_lowercase()
+				.contains(&removed.to_ascii_lowercase()),
+			"\ninput: {html}\ngot: {got}"
+		);
+	}
+
+	// And these must be kept as is
+	let kept = [
+		"<a href=\"https://example.com/\">a</a>",
+		"<a href=\"mailto:a@example.com\">a</a>",
+		"<a href=\"../a.html#b\">a</a>",
+		"<img src=\"a.png\" alt=\"a\">",
+		"<pre class=\"mermaid\">graph TD</pre>",
+		"<input type=\"checkbox\" checked=\"\" disabled=\"\">",
+	];
+	for html in kept {
+		assert_eq!(html, sanitize::clean(html), "\ninput: {html}");
+	}
+}
+
+#[test]
+fn test_sanitize_markdown() {
+	let ro = RenderOptions::parse_from(["mars", "--sanitize", "--figures"]);
+	let render = |md: &str| {
+		let mut html = String::new();
+		let options = markdown::options(&ro, &BTreeMap::new());
+		to_html(&mut html, md, options, false, &ro, None, |e| e);
+		sanitize::clean(&html)
+	};
+
+	// Markup written by mars itself survives
+	let tests = [
+		("![A cat](cat.png \"A cat\")", "<figure>"),
+		("![A cat](cat.png \"A cat\")", "<figcaption>"),
+		(
+			"- [x] done\n- [ ] todo",
+			"<input disabled=\"\" type=\"checkbox\" checked=\"\"",
+		),
+		("```mermaid\ngraph TD\n```", "<pre class=\"mermaid\">"),
+		(
+			"| a | b |\n|:-:|--:|\n| 1 | 2 |",
+			"<th style=\"text-align:center\">",
+		),
+		(
+			"| a | b |\n|:-:|--:|\n| 1 | 2 |",
+			"<td style=\"text-align:right\">",
+		),
+		(
+			"[a](https://example.com)",
+			"<a href=\"https://example.com\">",
+		),
+	];
+
+	for (md, expected) in tests {
+		let got = render(md);
+		assert!(
+			got.contains(expected),
+			"\ninput: {md}\nexpected: {expected}\ngot: {got}"
+		);
+	}
+
+	let removed = [
+		("[a](javascript:alert(1))", "javascript"),
+		("![a](javascript:alert(1))", "javascript"),
+		("[a](data:text/html,x)", "data:"),
+		("![a](data:image/png;base64,AAAA)", "data:"),
+		("<img src=x onerror=alert(1)>", "onerror"),
+		("a <script>alert(1)</script> b", "alert"),
+	];
+	for (md, fragment) in removed {
+		let got = render(md);
+		assert!(!got.contains(fragment), "\ninput: {md}\ngot: {got}");
+	}
+}
+
+#[test]
+fn test_wikilink_names() {
+	let names = site::Names::new([
+		"index.md",
+		"Guide/Setup.md",
+		"guide/intro.md",
+		"api/setup.md",
+		"./notes/Meeting Notes.markdown",
+	]);
+	let tests = [
+		("index", Ok(0)),
+		("INDEX.md", Ok(0)),
+		("intro", Ok(2)),
+		("guide/setup", Ok(1)),
+		("/guide/Setup", Ok(1)),
+		("api/setup.md", Ok(3)),
+		(" meeting notes ", Ok(4)),
+		("notes/meeting notes", Ok(4)),
+		("setup", Err("ambiguous")),
+		("other/setup", Err("no page")),
+		("missing", Err("no page")),
+	];
+
+	for (name, expected) in tests {
+		let got = names.find(name);
+		match (expected, &got) {
+			(Ok(i), Ok(got)) => assert_eq!(i, *got, "\ninput: {name}"),
+			(Err(e), Err(got)) => assert!(got.contains(e), "\ninput: {name}\ngot: {got}"),
+			_ => panic!("\ninput: {name}\nexpected: {expected:?}\ngot: {got:?}"),
+		}
+	}
+}
+
+#[test]
+fn test_wikilink_to_section() {
+	let dir = std::env::temp_dir().join(format!("mars-test-wikilink-{}", process::id()));
+	let (src, out) = (dir.join("src"), dir.join("out"));
+	fs::create_dir_all(&src).unwrap();
+	fs::write(src.join("page.md"), "See [[other#Some Heading]].\n").unwrap();
+	fs::write(src.join("other.md"), "# Other\n\n## Some Heading\n").unwrap();
+
+	let mut c = Cmd::parse_from([
+		"mars".as_ref(),
+		src.as_os_str(),
+		"-O".as_ref(),
+		out.as_os_str(),
+	]);
+	c.imply();
+	let ctx = Context {
+		ro: &c.opts,
+		fo: None,
+		format_error_exit: false,
+		minify: false,
+		xhtml: false,
+		validate: false,
+		clean: false,
+	};
+	let filter = Filter::new(&c.extensions, &c.include, &c.exclude, c.gitignore).unwrap();
+	convert_dir(&out, &src, true, filter, ctx).unwrap();
+
+	let page = fs::read_to_string(out.join("page.html")).unwrap();
+	let other = fs::read_to_string(out.join("other.html")).unwrap();
+	assert!(
+		page.contains("href=\"other.html#some-heading\""),
+		"\ngot: {page}"
+	);
+	assert!(other.contains("id=\"some-heading\""), "\ngot: {other}");
+
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_manifest_clean() {
+	let dir = std::env::temp_dir().join(format!("mars-test-manifest-{}", process::id()));
+	let out = dir.join("out");
+	for d in ["sub/deeper", "keep"] {
+		fs::create_dir_all(out.join(d)).unwrap();
+	}
+	let files = [
+		"secret.txt",
+		"out/written.html",
+		"out/old.html",
+		"out/sub/deeper/old.html",
+		"out/keep/old.html",
+		"out/keep/unlisted.txt",
+		"out/unlisted.html",
+	];
+	for f in files {
+		fs::write(dir.join(f), "x").unwrap();
+	}
+	let absolute = dir.join("secret.txt");
+	fs::write(
+		out.join(".mars-manifest"),
+		format!(
+			"written.html\nold.html\nsub/deeper/old.html\nkeep/old.html\n../secret.txt\nsub/../../secret.txt\n./old.html\n{}\n",
+			absolute.display()
+		),
+	)
+	.unwrap();
+
+	let mut manifest = Manifest::default();
+	manifest.add(&out, &out.join("written.html"));
+	manifest.add(&out, &out.join("new.html"));
+	manifest.clean(&out).unwrap();
+
+	let exists = [
+		("secret.txt", true),
+		("out/written.html", true),
+		("out/old.html", false),
+		("out/sub/deeper/old.html", false),
+		("out/sub/deeper", false),
+		("out/sub", false),
+		("out/keep/old.html", false),
+		("out/keep/unlisted.txt", true),
+		("out/keep", true),
+		("out/unlisted.html", true),
+		("out", true),
+	];
+	for (p, expected) in exists {
+		assert_eq!(expected, dir.join(p).exists(), "\npath: {p}");
+	}
+
+	let saved = fs::read_to_string(out.join(".mars-manifest")).unwrap();
+	assert_eq!("new.html\nwritten.html\n", saved);
+
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_epub_zip() {
+	fn crc32(data: &[u8]) -> u32 {
+		let mut c = !0_u32;
+		for &b in data {
+			c ^= u32::from(b);
+			for _ in 0..8 {
+				c = if c & 1 != 0 {
+					0xedb88320 ^ (c >> 1)
+				} else {
+					c >> 1
+				};
+			}
+		}
+		!c
+	}
+	let u16_at = |buf: &[u8], i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+	let u32_at = |buf: &[u8], i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+
+	assert_eq!(0xcbf43926, crc32(b"123456789"));
+
+	let dir = std::env::temp_dir().join(format!("mars-test-epub-{}", process::id()));
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+
+	let mut book = Book::new(epub::Metadata {
+		title: "Test".into(),
+		authors: vec!["Someone".into()],
+		language: "en".into(),
+		identifier: "urn:uuid:00000000-0000-0000-0000-000000000000".into(),
+		publisher: None,
+		description: None,
+		date: None,
+		modified: "2024-05-01T08:30:00Z".into(),
+	});
+	for name in ["a", "b"] {
+		book.add_chapter(
+			Path::new(&format!("{name}.xhtml")),
+			&dir.join(format!("{name}.md")),
+			name.to_uppercase(),
+			Vec::new(),
+			format!("<html><body><p>{name}</p></body></html>"),
+		);
+	}
+	let path = dir.join("test.epub");
+	book.write(&path).unwrap();
+	let buf = fs::read(&path).unwrap();
+
+	// mimetype comes first and is stored
+	assert_eq!(0x04034b50, u32_at(&buf, 0));
+	assert_eq!(0, u16_at(&buf, 8));
+	assert_eq!(8, u16_at(&buf, 26));
+	assert_eq!(0, u16_at(&buf, 28));
+	assert_eq!(b"mimetype", &buf[30..38]);
+	assert_eq!(b"application/epub+zip", &buf[38..58]);
+
+	// The local entries
+	let mut names = Vec::new();
+	let mut i = 0;
+	while u32_at(&buf, i) == 0x04034b50 {
+		let crc = u32_at(&buf, i + 14);
+		let size = u32_at(&buf, i + 18) as usize;
+		let name_len = usize::from(u16_at(&buf, i + 26));
+		let name = std::str::from_utf8(&buf[i + 30..i + 30 + name_len]).unwrap();
+		let data = &buf[i + 30 + name_len..i + 30 + name_len + size];
+		assert_eq!(crc32(data), crc, "\nentry: {name}");
+		names.push(name.to_owned());
+		i += 30 + name_len + size;
+	}
+	assert_eq!(
+		[
+			"mimetype",
+			"META-INF/container.xml",
+			"OEBPS/content.opf",
+			"OEBPS/nav.xhtml",
+		],
+		names[..4]
+	);
+	assert_eq!(6, names.len());
+
+	// The end of the central directory
+	let end = buf.len() - 22;
+	assert_eq!(0x06054b50, u32_at(&buf, end));
+	assert_eq!(6, u16_at(&buf, end + 8));
+	assert_eq!(6, u16_at(&buf, end + 10));
+	assert_eq!(i, u32_at(&buf, end + 16) as usize);
+	assert_eq!(end - i, u32_at(&buf, end + 12) as usize);
+
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_filter() {
+	let root = std::env::temp_dir().join(format!("mars-test-filter-{}", process::id()));
+	let _ = fs::remove_dir_all(&root);
+	fs::create_dir_all(root.join("docs/drafts")).unwrap();
+	fs::write(root.join(".marsignore"), "*.tmp.md\ndrafts/\nold-*.md\n").unwrap();
+	fs::write(
+		root.join("docs/.marsignore"),
+		"!old-keep.md\nlocal.md\n!drafts/\n",
+	)
+	.unwrap();
+
+	let filter = Filter::new(
+		&["md".to_owned()],
+		&["*.md".to_owned(), "docs/**".to_owned()],
+		&["secret*".to_owned(), "docs/private/**".to_owned()],
+		false,
+	)
+	.unwrap();
+
+	let tests = [
+		// Exclude beats include
+		("secret.md", false, Some("excluded by --exclude")),
+		("docs/secret.txt", false, Some("excluded by --exclude")),
+		("docs/private/a.md", false, Some("excluded by --exclude")),
+		("docs/private", true, None),
+		// Include only applies to files
+		("a.txt", false, Some("not matched by --include")),
+		("assets", true, None),
+		("docs/a.txt", false, None),
+		("a.md", false, None),
+		// The root ignore file
+		("a.tmp.md", false, Some("ignored by an ignore file")),
+		("docs/a.tmp.md", false, Some("ignored by an ignore file")),
+		("drafts", true, Some("ignored by an ignore file")),
+		("old-a.md", false, Some("ignored by an ignore file")),
+		// The nested ignore file
+		("local.md", false, None),
+		("docs/local.md", false, Some("ignored by an ignore file")),
+		// Negated patterns in the nested ignore file win
+		("docs/old-keep.md", false, None),
+		("docs/old-drop.md", false, Some("ignored by an ignore file")),
+		("docs/drafts", true, None),
+	];
+
+	for (p, is_dir, expected) in tests {
+		let got = filter.skip(&root, &root.join(p), is_dir);
+		assert_eq!(expected, got, "\npath: {p}");
+	}
+
+	let gitignore = Filter::new(&["md".to_owned()], &[], &[], true).unwrap();
+	fs::write(root.join(".gitignore"), "*.md\n").unwrap();
+	assert_eq!(
+		Some("ignored by an ignore file"),
+		gitignore.skip(&root, &root.join("a.md"), false)
+	);
+	assert_eq!(None, filter.skip(&root, &root.join("a.md"), false));
+
+	fs::remove_dir_all(&root).unwrap();
+}
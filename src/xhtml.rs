@@ -0,0 +1,409 @@
+// SPDX-License-Identifier: MIT
+
+use std::{
+	borrow::Cow,
+	fmt::Write,
+};
+
+use html5ever::data::NAMED_ENTITIES;
+
+use crate::markup::find_ignore_case;
+
+const XHTML_NS: &str = "http://www.w3.org/1999/xhtml";
+const SVG_NS: &str = "http://www.w3.org/2000/svg";
+const MATHML_NS: &str = "http://www.w3.org/1998/Math/MathML";
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+/// Elements that can't have contents.
+const VOID: &[&str] = &[
+	"area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+	"wbr",
+];
+
+/// Elements that end an open `<p>`.
+const CLOSES_P: &[&str] = &[
+	"address",
+	"article",
+	"aside",
+	"blockquote",
+	"details",
+	"div",
+	"dl",
+	"fieldset",
+	"figcaption",
+	"figure",
+	"footer",
+	"form",
+	"h1",
+	"h2",
+	"h3",
+	"h4",
+	"h5",
+	"h6",
+	"header",
+	"hgroup",
+	"hr",
+	"main",
+	"menu",
+	"nav",
+	"ol",
+	"p",
+	"pre",
+	"section",
+	"table",
+	"ul",
+];
+
+/// Reports whether starting `tag` ends the open element `open`.
+fn implies_end(open: &str, tag: &str) -> bool {
+	match open {
+		"p" => CLOSES_P.contains(&tag),
+		"li" => tag == "li",
+		"dt" | "dd" => matches!(tag, "dt" | "dd"),
+		"td" | "th" => matches!(tag, "td" | "th" | "tr"),
+		"tr" | "option" => tag == open,
+		_ => false,
+	}
+}
+
+struct Element {
+	/// The name as written.
+	name: String,
+	/// Whether this is an SVG or MathML element, whose names are case
+	/// sensitive.
+	foreign: bool,
+}
+
+/// Converts an HTML document into well-formed XHTML.
+///
+/// Void elements are self-closed, open elements are closed, attributes are
+/// quoted and character references are replaced with the characters, leaving
+/// only the ones predefined by XML.
+/// Scripts and styles containing markup characters are wrapped in CDATA
+/// sections hidden from HTML parsers in comments.
+pub fn convert(src: &str) -> String {
+	let mut buf = String::with_capacity(src.len() + src.len() / 8);
+	let mut stack = Vec::<Element>::new();
+	let mut rest = src;
+
+	while !rest.is_empty() {
+		let Some(i) = rest.find('<') else {
+			text(&mut buf, rest);
+			break;
+		};
+		text(&mut buf, &rest[..i]);
+		rest = &rest[i..];
+
+		if let Some(s) = rest.strip_prefix("<!--") {
+			let (comment, r) = s.split_once("-->").unwrap_or((s, ""));
+			rest = r;
+			// `--` is not allowed in XML comments
+			let mut comment = comment.replace("--", "- -");
+			if comment.ends_with('-') {
+				comment.push(' ');
+			}
+			let _ = write!(buf, "<!--{comment}-->");
+			continue;
+		}
+		if let Some(s) = rest.strip_prefix("<![CDATA[") {
+			let (data, r) = s.split_once("]]>").unwrap_or((s, ""));
+			rest = r;
+			// HTML parsers treat it as a comment outside SVG and MathML
+			if stack.last().is_some_and(|e| e.foreign) {
+				let _ = write!(buf, "<![CDATA[{data}]]>");
+			}
+			continue;
+		}
+		if rest.starts_with("<!") || rest.starts_with("<?") {
+			let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+			let decl = &rest[..end];
+			rest = &rest[end..];
+			if decl.len() > 9 && decl[..9].eq_ignore_ascii_case("<!doctype") {
+				buf.push_str("<!DOCTYPE html>");
+			}
+			continue;
+		}
+
+		let closing = rest.starts_with("</");
+		let s = &rest[if closing { 2 } else { 1 }..];
+		let len = s
+			.find(|c: char| c.is_ascii_whitespace() || matches!(c, '/' | '>'))
+			.unwrap_or(s.len());
+		let valid = len > 0
+			&& s.as_bytes()[0].is_ascii_alphabetic()
+			&& s[..len]
+				.chars()
+				.all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'));
+		if !valid {
+			buf.push_str("&lt;");
+			rest = &rest[1..];
+			continue;
+		}
+
+		let foreign = match stack.last() {
+			Some(e) if e.foreign => e.name != "foreignObject",
+			_ => false,
+		};
+		let name = &s[..len];
+		let lower = name.to_ascii_lowercase();
+
+		if closing {
+			rest = s[len..].find('>').map_or("", |i| &s[len + i + 1..]);
+			let pos = stack.iter().rposition(|e| {
+				if e.foreign {
+					e.name == name
+				} else {
+					e.name == lower
+				}
+			});
+			if let Some(pos) = pos {
+				for e in stack.drain(pos..).rev() {
+					let _ = write!(buf, "</{}>", e.name);
+				}
+			}
+			continue;
+		}
+
+		let foreign = foreign || lower == "svg" || lower == "math";
+		let name = if foreign { name.to_owned() } else { lower };
+
+		if !foreign {
+			while stack
+				.last()
+				.is_some_and(|e| !e.foreign && implies_end(&e.name, &name))
+			{
+				let e = stack.pop().unwrap();
+				let _ = write!(buf, "</{}>", e.name);
+			}
+		}
+
+		let (attrs, self_closing, len) = attributes(&s[len..], foreign);
+		rest = &s[name.len() + len..];
+
+		let _ = write!(buf, "<{name}");
+		let has = |n: &str| attrs.iter().any(|(k, _)| k == n);
+		match name.as_str() {
+			"html" if !has("xmlns") => {
+				let _ = write!(buf, " xmlns=\"{XHTML_NS}\"");
+			}
+			"svg" if !has("xmlns") => {
+				let _ = write!(buf, " xmlns=\"{SVG_NS}\"");
+			}
+			"math" if !has("xmlns") => {
+				let _ = write!(buf, " xmlns=\"{MATHML_NS}\"");
+			}
+			_ => (),
+		}
+		// Descendants may use it too
+		if name == "svg" && !has("xmlns:xlink") {
+			let _ = write!(buf, " xmlns:xlink=\"{XLINK_NS}\"");
+		}
+		for (k, v) in &attrs {
+			let _ = write!(buf, " {k}=\"");
+			escape(&mut buf, v, true);
+			buf.push('"');
+		}
+		if name == "html" && !has("xml:lang") {
+			if let Some((_, lang)) = attrs.iter().find(|(k, _)| k == "lang") {
+				buf.push_str(" xml:lang=\"");
+				escape(&mut buf, lang, true);
+				buf.push('"');
+			}
+		}
+
+		if (!foreign && VOID.contains(&name.as_str())) || (foreign && self_closing) {
+			buf.push_str(" />");
+			continue;
+		}
+		buf.push('>');
+
+		if foreign {
+			stack.push(Element { name, foreign });
+			continue;
+		}
+
+		match name.as_str() {
+			"script" | "style" => {
+				let end = find_ignore_case(rest, &format!("</{name}")).unwrap_or(rest.len());
+				buf.push_str(&raw_text(&name, &rest[..end]));
+				rest = &rest[end..];
+			}
+			"textarea" | "title" => {
+				let end = find_ignore_case(rest, &format!("</{name}")).unwrap_or(rest.len());
+				text(&mut buf, &rest[..end]);
+				rest = &rest[end..];
+			}
+			_ => (),
+		}
+		stack.push(Element { name, foreign });
+	}
+
+	for e in stack.into_iter().rev() {
+		let _ = write!(buf, "</{}>", e.name);
+	}
+
+	buf
+}
+
+/// Parses the attributes of a tag, returning them with whether the tag is
+/// self-closing and its length.
+///
+/// Boolean attributes get their name as the value, and duplicates are
+/// dropped like HTML parsers do.
+fn attributes(s: &str, foreign: bool) -> (Vec<(String, String)>, bool, usize) {
+	let mut attrs = Vec::<(String, String)>::new();
+	let mut self_closing = false;
+	let mut rest = s;
+
+	loop {
+		rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+		if rest.is_empty() {
+			break;
+		}
+		if let Some(r) = rest.strip_prefix('>') {
+			rest = r;
+			break;
+		}
+		if let Some(r) = rest.strip_prefix('/') {
+			rest = r;
+			self_closing = rest.starts_with('>');
+			continue;
+		}
+
+		let n = rest
+			.find(|c: char| c.is_ascii_whitespace() || matches!(c, '=' | '>' | '/'))
+			.unwrap_or(rest.len())
+			.max(1);
+		let name = &rest[..n];
+		rest = rest[n..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+
+		let mut value = None;
+		if let Some(v) = rest.strip_prefix('=') {
+			let v = v.trim_start_matches(|c: char| c.is_ascii_whitespace());
+			let (val, len) = match v.chars().next() {
+				Some(q @ ('"' | '\'')) => {
+					let close = v[1..].find(q).map_or(v.len(), |i| i + 1);
+					(&v[1..close], usize::min(close + 1, v.len()))
+				}
+				_ => {
+					let n = v
+						.find(|c: char| c.is_ascii_whitespace() || c == '>')
+						.unwrap_or(v.len());
+					(&v[..n], n)
+				}
+			};
+			value = Some(val);
+			rest = &v[len..];
+		}
+
+		let valid = name
+			.chars()
+			.all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+			&& !name.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.');
+		if !valid {
+			continue;
+		}
+		let name = if foreign {
+			name.to_owned()
+		} else {
+			name.to_ascii_lowercase()
+		};
+		if attrs.iter().any(|(k, _)| *k == name) {
+			continue;
+		}
+		let value = match value {
+			Some(v) => decode(v),
+			None => name.clone(),
+		};
+		attrs.push((name, value));
+	}
+
+	(attrs, self_closing, s.len() - rest.len())
+}
+
+/// Returns the contents of a `<script>` or `<style>` element as written by
+/// [convert].
+pub fn raw_text<'a>(tag: &str, s: &'a str) -> Cow<'a, str> {
+	if !s.contains(['<', '&']) {
+		return Cow::Borrowed(s);
+	}
+
+	let s = s.replace("]]>", "]]]]><![CDATA[>");
+	Cow::Owned(if tag == "style" {
+		format!("/*<![CDATA[*/{s}/*]]>*/")
+	} else {
+		format!("//<![CDATA[\n{s}\n//]]>")
+	})
+}
+
+/// Replaces the character references in `s` with the characters.
+fn decode(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	let mut rest = s;
+
+	while let Some(i) = rest.find('&') {
+		out.push_str(&rest[..i]);
+		rest = &rest[i..];
+		match char_ref(rest) {
+			Some((c, len)) => {
+				out.push_str(&c);
+				rest = &rest[len..];
+			}
+			None => {
+				out.push('&');
+				rest = &rest[1..];
+			}
+		}
+	}
+
+	out.push_str(rest);
+	out
+}
+
+/// Decodes the character reference at the start of `s`, returning it with
+/// its length.
+fn char_ref(s: &str) -> Option<(String, usize)> {
+	let end = s.find(';')?;
+	let name = &s[1..end];
+
+	if let Some(num) = name.strip_prefix('#') {
+		let n = match num.strip_prefix(['x', 'X']) {
+			Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+			None => num.parse().ok()?,
+		};
+		let c = char::from_u32(n).filter(|&c| is_xml_char(c))?;
+		return Some((c.to_string(), end + 1));
+	}
+
+	if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric()) {
+		return None;
+	}
+	let &(a, b) = NAMED_ENTITIES.get(&s[1..=end])?;
+	let mut out: String = char::from_u32(a).into_iter().collect();
+	out.extend(char::from_u32(b).filter(|_| b != 0));
+	Some((out, end + 1))
+}
+
+fn is_xml_char(c: char) -> bool {
+	matches!(c, '\t' | '\n' | '\r') || (c >= ' ' && c != '\u{FFFE}' && c != '\u{FFFF}')
+}
+
+/// Writes text, with its character references decoded and escaped again.
+fn text(buf: &mut String, s: &str) {
+	escape(buf, &decode(s), false);
+}
+
+/// Escapes text or an attribute value for XML. Characters not allowed in
+/// XML are dropped.
+fn escape(buf: &mut String, s: &str, attr: bool) {
+	for c in s.chars() {
+		match c {
+			'&' => buf.push_str("&amp;"),
+			'<' => buf.push_str("&lt;"),
+			'>' => buf.push_str("&gt;"),
+			'"' if attr => buf.push_str("&quot;"),
+			c if is_xml_char(c) => buf.push(c),
+			_ => (),
+		}
+	}
+}